@@ -0,0 +1,308 @@
+//! Derive macros for the `duktape` crate, exposing Rust types as JavaScript classes.
+//!
+//! `#[derive(JsClass)]` names the class and exposes fields as properties, `#[js_methods]` on an
+//! `impl` block exposes its functions. Both are configured with `#[js(...)]` attributes:
+//!
+//! - `name = "..."`: the JavaScript name of the class, property or function.
+//! - `get`, `set`: on a field, exposes it as a property read with `Clone` and `IntoJs`, and
+//!   assigned with `FromJs`. On a method, makes it the getter or setter of a property, named
+//!   after the method without its `set_` prefix by default.
+//! - `constructor`: makes an associated function returning `Self`, or a `Result` of it, the
+//!   constructor.
+//! - `skip`: leaves a function out.
+//!
+//! Methods taking `&self` or `&mut self` go on the prototype, other functions on the
+//! constructor. Arguments are converted with `FromJs` and results with `IntoJs`, a `&CallContext`
+//! argument receives the context of the call instead.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, FnArg, Ident, ImplItem, ImplItemFn, ItemImpl, LitStr,
+    Member, Type,
+};
+
+/// Implements `duktape::JsClass`, see the crate documentation.
+#[proc_macro_derive(JsClass, attributes(js))]
+pub fn derive_js_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_js_class(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Implements `duktape::JsMethods` for the type of an `impl` block, see the crate documentation.
+#[proc_macro_attribute]
+pub fn js_methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(input as ItemImpl);
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return Error::new_spanned(args, "#[js_methods] takes no arguments")
+            .into_compile_error()
+            .into();
+    }
+    expand_js_methods(&mut item).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// The options of the `#[js(...)]` attributes of an item.
+#[derive(Default)]
+struct JsAttrs {
+    name: Option<LitStr>,
+    constructor: bool,
+    get: bool,
+    set: bool,
+    skip: bool,
+}
+
+impl JsAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<JsAttrs> {
+        let mut res = JsAttrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("js")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    res.name = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("constructor") {
+                    res.constructor = true;
+                } else if meta.path.is_ident("get") {
+                    res.get = true;
+                } else if meta.path.is_ident("set") {
+                    res.set = true;
+                } else if meta.path.is_ident("skip") {
+                    res.skip = true;
+                } else {
+                    return Err(meta.error("unknown js option"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(res)
+    }
+}
+
+fn expand_js_class(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = JsAttrs::parse(&input.attrs)?;
+    if attrs.constructor || attrs.get || attrs.set || attrs.skip {
+        return Err(Error::new_spanned(&input.ident, "only `name` applies to a type"));
+    }
+    let ident = &input.ident;
+    let name = match attrs.name {
+        Some(name) => name.value(),
+        None => ident.to_string(),
+    };
+
+    let mut accessors = Vec::new();
+    if let Data::Struct(data) = &input.data {
+        for (idx, field) in data.fields.iter().enumerate() {
+            let attrs = JsAttrs::parse(&field.attrs)?;
+            if attrs.constructor || attrs.skip {
+                return Err(Error::new(field.span(), "only `name`, `get` and `set` apply to a field"));
+            }
+            if !attrs.get && !attrs.set {
+                if attrs.name.is_some() {
+                    return Err(Error::new(field.span(), "a named field needs `get` or `set`"));
+                }
+                continue;
+            }
+            let (member, name) = match (&field.ident, attrs.name) {
+                (_, Some(name)) => (member(field.ident.as_ref(), idx), name.value()),
+                (Some(ident), None) => (Member::Named(ident.clone()), ident.to_string()),
+                (None, None) => return Err(Error::new(field.span(), "a tuple field needs a `name`")),
+            };
+            let ty = &field.ty;
+            if attrs.get {
+                accessors.push(quote! {
+                    .getter(#name, |this: &mut Self, call: &::duktape::CallContext| {
+                        ::duktape::IntoJs::into_js(::std::clone::Clone::clone(&this.#member), call.context())
+                    })
+                });
+            }
+            if attrs.set {
+                accessors.push(quote! {
+                    .setter(#name, |this: &mut Self, value: ::duktape::Value, _call: &::duktape::CallContext| {
+                        this.#member = <#ty as ::duktape::FromJs>::from_js(&value)?;
+                        ::std::result::Result::Ok(())
+                    })
+                });
+            }
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::duktape::JsClass for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn define_fields(builder: ::duktape::ClassBuilder<'_, Self>) -> ::duktape::ClassBuilder<'_, Self> {
+                builder #(#accessors)*
+            }
+        }
+    })
+}
+
+fn member(ident: Option<&Ident>, idx: usize) -> Member {
+    match ident {
+        Some(ident) => Member::Named(ident.clone()),
+        None => Member::Unnamed(idx.into()),
+    }
+}
+
+fn expand_js_methods(item: &mut ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new_spanned(path, "#[js_methods] goes on an inherent impl block"));
+    }
+
+    let mut registrations = Vec::new();
+    let mut has_constructor = false;
+    for impl_item in &mut item.items {
+        let method = match impl_item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+        let attrs = JsAttrs::parse(&method.attrs)?;
+        // The attributes are ours, the compiler doesn't know about them
+        method.attrs.retain(|attr| !attr.path().is_ident("js"));
+        if attrs.skip {
+            continue;
+        }
+        if attrs.constructor {
+            if has_constructor {
+                return Err(Error::new_spanned(&method.sig.ident, "a class has only one constructor"));
+            }
+            has_constructor = true;
+        }
+        registrations.push(registration(method, &attrs)?);
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let self_ty = &item.self_ty;
+    Ok(quote! {
+        #item
+
+        impl #impl_generics ::duktape::JsMethods for #self_ty #where_clause {
+            fn define_methods(builder: ::duktape::ClassBuilder<'_, Self>) -> ::duktape::ClassBuilder<'_, Self> {
+                builder #(#registrations)*
+            }
+        }
+    })
+}
+
+/// Whether `ty` is a reference to a `CallContext`.
+fn is_call_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => match &*reference.elem {
+            Type::Path(path) => path.path.segments.last().is_some_and(|s| s.ident == "CallContext"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The `ClassBuilder` call registering `method`.
+fn registration(method: &ImplItemFn, attrs: &JsAttrs) -> syn::Result<TokenStream2> {
+    let sig = &method.sig;
+    let ident = &sig.ident;
+    if sig.asyncness.is_some() || sig.variadic.is_some() {
+        return Err(Error::new_spanned(ident, "only plain functions can be exposed"));
+    }
+    if sig.generics.type_params().next().is_some() || sig.generics.const_params().next().is_some() {
+        return Err(Error::new_spanned(&sig.generics, "generic functions can't be exposed"));
+    }
+    let receiver = match sig.receiver() {
+        Some(receiver) if receiver.reference.is_none() || receiver.colon_token.is_some() => {
+            return Err(Error::new_spanned(receiver, "methods take `&self` or `&mut self`"));
+        }
+        Some(_) => true,
+        None => false,
+    };
+
+    // The setter gets its value from the assignment, everything else from the arguments
+    let mut args = Vec::new();
+    let mut arity = 0_usize;
+    for input in &sig.inputs {
+        let ty = match input {
+            FnArg::Typed(arg) => &arg.ty,
+            FnArg::Receiver(_) => continue,
+        };
+        if is_call_context(ty) {
+            args.push(quote!(call));
+        } else if attrs.set {
+            args.push(quote!(<#ty as ::duktape::FromJs>::from_js(&value)?));
+            arity += 1;
+        } else {
+            args.push(quote!(::duktape::__private::argument::<#ty>(call.args(), #arity)?));
+            arity += 1;
+        }
+    }
+    let this = if receiver { Some(quote!(this,)) } else { None };
+    let call = quote!(Self::#ident(#this #(#args),*));
+
+    let kinds = [attrs.constructor, attrs.get, attrs.set].iter().filter(|kind| **kind).count();
+    if kinds > 1 {
+        return Err(Error::new_spanned(ident, "`constructor`, `get` and `set` are exclusive"));
+    }
+    if attrs.constructor {
+        if receiver {
+            return Err(Error::new_spanned(ident, "a constructor doesn't take `self`"));
+        }
+        if attrs.name.is_some() {
+            return Err(Error::new_spanned(ident, "a constructor is named after its class"));
+        }
+        return Ok(quote! {
+            .constructor(|call: &::duktape::CallContext| {
+                ::duktape::__private::check_arity(call.args(), #arity)?;
+                ::duktape::__private::IntoInstance::<Self>::into_instance(#call)
+            })
+        });
+    }
+
+    if (attrs.get || attrs.set) && !receiver {
+        return Err(Error::new_spanned(ident, "getters and setters take `&self` or `&mut self`"));
+    }
+    let name = match &attrs.name {
+        Some(name) => name.value(),
+        None if attrs.set => {
+            let name = ident.to_string();
+            match name.strip_prefix("set_") {
+                Some(property) => String::from(property),
+                None => name,
+            }
+        }
+        None => ident.to_string(),
+    };
+    if attrs.get {
+        if arity != 0 {
+            return Err(Error::new_spanned(ident, "a getter takes no arguments"));
+        }
+        Ok(quote! {
+            .getter(#name, |this: &mut Self, call: &::duktape::CallContext| {
+                ::duktape::IntoJs::into_js(#call, call.context())
+            })
+        })
+    } else if attrs.set {
+        if arity != 1 {
+            return Err(Error::new_spanned(ident, "a setter takes exactly one argument"));
+        }
+        Ok(quote! {
+            .setter(#name, |this: &mut Self, value: ::duktape::Value, call: &::duktape::CallContext| {
+                ::duktape::IntoJs::into_js(#call, call.context())?;
+                ::std::result::Result::Ok(())
+            })
+        })
+    } else if receiver {
+        Ok(quote! {
+            .method(#name, |this: &mut Self, call: &::duktape::CallContext| {
+                ::duktape::__private::check_arity(call.args(), #arity)?;
+                ::duktape::IntoJs::into_js(#call, call.context())
+            })
+        })
+    } else {
+        Ok(quote! {
+            .static_method(#name, |call: &::duktape::CallContext| {
+                ::duktape::__private::check_arity(call.args(), #arity)?;
+                ::duktape::IntoJs::into_js(#call, call.context())
+            })
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use crate::context::Context;
+#[cfg(feature = "exec-timeout")]
+use crate::interrupt::InterruptHandle;
+use crate::types::OwnedValue;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce(&Context) + Send>;
+
+enum Message {
+    Job(Job),
+    Shutdown,
+}
+
+/// A `Context` living on its own OS thread.
+///
+/// The context never leaves the thread, it's reached through a `ContextHandle` that can be sent
+/// and shared across threads. Dropping the `ContextThread` shuts the thread down once the queued
+/// work is done.
+///
+/// ```ignore
+/// let worker = ContextThread::spawn(Context::new)?;
+/// let handle = worker.handle();
+/// let sum = handle.call(|ctx| -> DukResult<i64> { Ok(ctx.eval_string("1 + 2")?.into()) })??;
+/// ```
+pub struct ContextThread {
+    handle: ContextHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ContextThread {
+    /// Starts a thread running a context created by `factory` on it.
+    pub fn spawn<F>(factory: F) -> anyhow::Result<ContextThread>
+    where
+        F: FnOnce() -> anyhow::Result<Context> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name(String::from("duktape-context"))
+            .spawn(move || {
+                let ctx = match factory() {
+                    Ok(ctx) => ctx,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(ContextHandle {
+                    sender,
+                    #[cfg(feature = "exec-timeout")]
+                    interrupt: ctx.interrupt_handle(),
+                }));
+                for message in receiver {
+                    match message {
+                        Message::Job(job) => job(&ctx),
+                        Message::Shutdown => break,
+                    }
+                }
+            })?;
+
+        let handle = match ready_rx.recv() {
+            Ok(res) => res?,
+            Err(_) => match thread.join() {
+                Err(payload) => panic::resume_unwind(payload),
+                Ok(_) => return Err(anyhow::anyhow!("Could not create context")),
+            },
+        };
+        Ok(ContextThread {
+            handle,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns a handle to run work on the context.
+    pub fn handle(&self) -> ContextHandle {
+        self.handle.clone()
+    }
+
+    /// Stops the thread after the work queued so far is done, and waits for it to finish. A panic
+    /// on the thread outside of a `ContextHandle::call` is propagated to the caller.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.handle.sender.send(Message::Shutdown);
+            if let Err(payload) = thread.join() {
+                if !thread::panicking() {
+                    panic::resume_unwind(payload);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ContextThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A handle to a `ContextThread`, which can be cloned and used from any thread.
+#[derive(Clone)]
+pub struct ContextHandle {
+    sender: Sender<Message>,
+    #[cfg(feature = "exec-timeout")]
+    interrupt: InterruptHandle,
+}
+
+impl ContextHandle {
+    /// Runs `f` with the context on its thread and waits for the result. Fails if the thread was
+    /// shut down. If `f` panics the panic is resumed on the calling thread.
+    pub fn call<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&Context) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let job: Job = Box::new(move |ctx| {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| f(ctx)));
+            let _ = reply_tx.send(res);
+        });
+        if self.sender.send(Message::Job(job)).is_err() {
+            return Err(anyhow::anyhow!("The context thread is shut down"));
+        }
+        match reply_rx.recv() {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(payload)) => panic::resume_unwind(payload),
+            Err(_) => Err(anyhow::anyhow!("The context thread is shut down")),
+        }
+    }
+
+    /// Evaluates a string on the context, returning the result detached from it.
+    pub fn eval_string<S: Into<String>>(&self, code: S) -> anyhow::Result<OwnedValue> {
+        let code = code.into();
+        let res = self.call(move |ctx| ctx.eval_string(&code).map(|v| OwnedValue::from(&v)))?;
+        Ok(res?)
+    }
+
+    /// Aborts the script currently running on the context, see `InterruptHandle`.
+    #[cfg(feature = "exec-timeout")]
+    pub fn interrupt(&self) {
+        self.interrupt.interrupt();
+    }
+}
@@ -0,0 +1,156 @@
+use crate::context::Context;
+use crate::extensions::Extensions;
+use crate::heap::{
+    alloc_trampoline, fatal_trampoline, free_trampoline, realloc_trampoline, AllocFn, Allocator,
+    FatalCallback, FatalFn, FreeFn, Heap, HeapState, MemoryTracker, ReallocFn,
+};
+use crate::interrupt::ExecState;
+use crate::DukResult;
+use dukbind::duk_create_heap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::ptr::{self, NonNull};
+use std::rc::Weak;
+#[cfg(feature = "exec-timeout")]
+use std::time::Duration;
+
+type InitHook = Box<dyn FnOnce(&Context) -> DukResult<()>>;
+
+/// Builder for a `Context` with a configurable duktape heap.
+///
+/// ```ignore
+/// let ctx = ContextBuilder::new()
+///     .with_init(|ctx| ctx.eval_string("var VERSION = '1.0'").map(|_| ()))
+///     .build()?;
+/// ```
+pub struct ContextBuilder {
+    allocator: Option<Allocator>,
+    memory_limit: Option<usize>,
+    #[cfg(feature = "exec-timeout")]
+    time_limit: Option<Duration>,
+    user_data: *mut c_void,
+    fatal_handler: Option<FatalFn>,
+    on_fatal: Option<FatalCallback>,
+    init_hooks: Vec<InitHook>,
+}
+
+impl ContextBuilder {
+    /// Creates a builder with duktape's default heap configuration.
+    pub fn new() -> Self {
+        Self {
+            allocator: None,
+            memory_limit: None,
+            #[cfg(feature = "exec-timeout")]
+            time_limit: None,
+            user_data: ptr::null_mut(),
+            fatal_handler: None,
+            on_fatal: None,
+            init_hooks: Vec::new(),
+        }
+    }
+
+    /// Use custom memory management functions for the heap. The functions receive the pointer set
+    /// with `user_data` as their first argument.
+    pub fn allocator(mut self, alloc: AllocFn, realloc: ReallocFn, free: FreeFn) -> Self {
+        self.allocator = Some(Allocator { alloc, realloc, free });
+        self
+    }
+
+    /// Limits the memory the heap can hold at once to `bytes`. Allocations going over the limit
+    /// are refused, and scripts exhausting the budget fail with `DukErrorCode::OutOfMemory`.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Limits how long each script execution can run. Scripts running over it fail with
+    /// `DukErrorCode::Timeout`.
+    #[cfg(feature = "exec-timeout")]
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Sets the heap user data pointer passed to the custom memory functions and fatal handler.
+    pub fn user_data(mut self, user_data: *mut c_void) -> Self {
+        self.user_data = user_data;
+        self
+    }
+
+    /// Sets a raw function called on fatal errors, after `on_fatal` was notified. It receives the
+    /// pointer set with `user_data` as its first argument. If it returns, the process is aborted.
+    pub fn fatal_handler(mut self, handler: FatalFn) -> Self {
+        self.fatal_handler = Some(handler);
+        self
+    }
+
+    /// Sets a callback notified with the message of fatal errors, e.g. to log them.
+    ///
+    /// Errors thrown by scripts and by the operations of this crate are returned as `DukError`s.
+    /// Fatal errors are left to internal failures of duktape, after which it can't be resumed: the
+    /// callbacks are called and the process is aborted.
+    pub fn on_fatal<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&str) + 'static,
+    {
+        self.on_fatal = Some(Box::new(callback));
+        self
+    }
+
+    /// Adds a hook that runs once right after the heap is created, in the order they were added.
+    /// Useful to install globals the scripts expect.
+    pub fn with_init<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(&Context) -> DukResult<()> + 'static,
+    {
+        self.init_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Creates the heap and runs the init hooks against it.
+    pub fn build(self) -> anyhow::Result<Context> {
+        let state = Box::new(HeapState {
+            user_data: self.user_data,
+            allocator: self.allocator,
+            fatal_handler: self.fatal_handler,
+            on_fatal: self.on_fatal,
+            memory: MemoryTracker::new(self.memory_limit),
+            exec: ExecState::default(),
+            object_refs: RefCell::new(HashMap::new()),
+            extensions: Extensions::new(),
+            heap: RefCell::new(Weak::new()),
+        });
+        #[cfg(feature = "exec-timeout")]
+        state.exec.time_limit.set(self.time_limit);
+        let udata = &*state as *const HeapState as *mut c_void;
+
+        // Memory always goes through the trampolines so it can be accounted for, they forward to
+        // the custom allocator if there is one.
+        let raw = unsafe {
+            duk_create_heap(
+                Some(alloc_trampoline),
+                Some(realloc_trampoline),
+                Some(free_trampoline),
+                udata,
+                Some(fatal_trampoline),
+            )
+        };
+        let ctx = match NonNull::new(raw) {
+            Some(ctx) => ctx,
+            None => return Err(anyhow::anyhow!("Could not create context")),
+        };
+
+        let context = Context::from_heap(Heap { ctx, state });
+        for hook in self.init_hooks {
+            hook(&context)?;
+        }
+        Ok(context)
+    }
+}
+
+impl Default for ContextBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
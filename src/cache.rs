@@ -0,0 +1,182 @@
+use crate::context::Context;
+use crate::script::{fnv1a, Script, DUKTAPE_VERSION};
+use crate::DukResult;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+const EXTENSION: &str = "dkbc";
+
+/// Number of entries written by this process, to give each write its own temporary file.
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of compiled scripts, reused across process starts.
+///
+/// Entries are keyed by the source, filename and duktape version, so changing any of them compiles
+/// the script again. Each entry also stores the source it was compiled from, which is compared in
+/// full on a hit, so two sources with the same key never get each other's bytecode. Corrupted or
+/// stale entries are detected when loading and replaced. When a size
+/// limit is set, the least recently used entries are evicted to stay under it.
+///
+/// Caching is best effort: failing to read or write the directory never fails a compilation.
+///
+/// The entries are loaded with `Context::load_bytecode`, which trusts the bytecode. Anyone able to
+/// write to the directory can plant an entry that takes over the process loading it, so it must
+/// only be writable by trusted users.
+///
+/// ```ignore
+/// // The directory is private to the tool
+/// let cache = unsafe { ScriptCache::new("/var/cache/my-tool")? }.max_size(64 * 1024 * 1024);
+/// let lib = cache.compile(&ctx, LODASH_SOURCE, "vendor/lodash.js")?;
+/// lib.run()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ScriptCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+}
+
+impl ScriptCache {
+    /// Creates a cache storing its entries in `dir`, creating the directory if needed.
+    ///
+    /// # Safety
+    /// `dir` and its entries must only be writable by trusted users, see `ScriptCache`.
+    pub unsafe fn new<P: Into<PathBuf>>(dir: P) -> io::Result<ScriptCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(ScriptCache { dir, max_size: None })
+    }
+
+    /// Limits the total size of the entries in bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// The directory holding the entries.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the cached compilation of `source`, compiling and storing it if there is none.
+    pub fn compile(&self, ctx: &Context, source: &str, filename: &str) -> DukResult<Script> {
+        let path = self.entry_path(source, filename);
+        if let Some(script) = self.load(ctx, &path, source, filename) {
+            return Ok(script);
+        }
+
+        let script = ctx.compile(source, filename)?;
+        if let Ok(bytecode) = script.to_bytecode() {
+            if self.store(&path, &bytecode, source).is_ok() {
+                let _ = self.evict();
+            }
+        }
+        Ok(script)
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) -> io::Result<()> {
+        for (path, _, _) in self.entries()? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, source: &str, filename: &str) -> PathBuf {
+        let mut hash = fnv1a(0xcbf2_9ce4_8422_2325, &DUKTAPE_VERSION.to_le_bytes());
+        hash = fnv1a(hash, filename.as_bytes());
+        hash = fnv1a(hash, &[0]);
+        hash = fnv1a(hash, source.as_bytes());
+        self.dir.join(format!("{:016x}.{}", hash, EXTENSION))
+    }
+
+    fn load(&self, ctx: &Context, path: &Path, source: &str, filename: &str) -> Option<Script> {
+        let entry = fs::read(path).ok()?;
+        let loaded = match split_entry(&entry) {
+            Some((bytecode, stored)) if stored == source.as_bytes() => {
+                // The cache directory is trusted, see `ScriptCache`
+                unsafe { ctx.load_bytecode(bytecode).ok() }
+            }
+            _ => None,
+        };
+        match loaded {
+            Some(script) if script.filename() == filename => {
+                // Bump the entry as recently used
+                if let Ok(file) = File::options().write(true).open(path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(script)
+            }
+            _ => {
+                // Stale or corrupted, it gets replaced
+                let _ = fs::remove_file(path);
+                None
+            }
+        }
+    }
+
+    fn store(&self, path: &Path, bytecode: &[u8], source: &str) -> io::Result<()> {
+        // Write to a temporary file first so other processes never see partial entries. Its name
+        // is unique to this write, other threads may be storing the same entry.
+        let write = WRITES.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{}.tmp", process::id(), write));
+        let res = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(bytecode)?;
+                file.write_all(source.as_bytes())?;
+                file.write_all(&(source.len() as u64).to_le_bytes())
+            })
+            .and_then(|_| fs::rename(&tmp, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+
+    fn entries(&self) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((path, meta.len(), modified));
+        }
+        Ok(entries)
+    }
+
+    /// Removes the least recently used entries until the cache fits its size limit.
+    fn evict(&self) -> io::Result<()> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(()),
+        };
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in entries {
+            if total <= max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Splits an entry into its bytecode and the source it was compiled from. The source comes after
+/// the bytecode, followed by its length.
+fn split_entry(entry: &[u8]) -> Option<(&[u8], &[u8])> {
+    let trailer = entry.len().checked_sub(8)?;
+    let source_len = u64::from_le_bytes(entry[trailer..].try_into().unwrap());
+    let start = trailer.checked_sub(usize::try_from(source_len).ok()?)?;
+    Some((&entry[..start], &entry[start..trailer]))
+}
@@ -0,0 +1,395 @@
+use crate::context::{Context, Object};
+use crate::error::{DukError, DukErrorCode};
+use crate::function::{delete_hidden, drop_boxed, hidden_pointer, CallContext, Function};
+use crate::types::Value;
+use crate::DukResult;
+use dukbind::{duk_context, duk_ret_t};
+use std::any::Any;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+/// Hidden property of class instances holding their boxed Rust value.
+const INSTANCE_KEY: &[u8] = b"\xFFduktape-rs:instance";
+
+/// The Rust value of an instance, as a `RefCell<T>` for the class `T`. Shared, for a method
+/// borrowing it to keep it alive if a script calls the finalizer by hand.
+type Instance = Rc<dyn Any>;
+
+type Constructor<T> = Box<dyn Fn(&CallContext) -> DukResult<T>>;
+type Method<T> = Box<dyn Fn(&mut T, &CallContext) -> DukResult<Value>>;
+type StaticMethod = Box<dyn Fn(&CallContext) -> DukResult<Value>>;
+type Setter<T> = Box<dyn Fn(&mut T, Value, &CallContext) -> DukResult<()>>;
+
+/// The getter and setter of a property.
+struct Accessor<T> {
+    name: String,
+    getter: Option<Method<T>>,
+    setter: Option<Setter<T>>,
+}
+
+/// Builder for a JavaScript class backed by the Rust type `T`, see `Context::register_class`.
+pub struct ClassBuilder<'a, T> {
+    context: &'a Context,
+    name: String,
+    constructor: Option<Constructor<T>>,
+    methods: Vec<(String, Method<T>)>,
+    static_methods: Vec<(String, StaticMethod)>,
+    accessors: Vec<Accessor<T>>,
+}
+
+impl<'a, T: 'static> ClassBuilder<'a, T> {
+    /// Sets the function building the Rust value of instances created with `new`. Without one,
+    /// instances can only be created from Rust with `Class::instance`.
+    pub fn constructor<F>(mut self, constructor: F) -> Self
+    where
+        F: Fn(&CallContext) -> DukResult<T> + 'static,
+    {
+        self.constructor = Some(Box::new(constructor));
+        self
+    }
+
+    /// Adds a method to the prototype. It's called with the Rust value of the instance it's
+    /// called on, and throws a `TypeError` when called on anything else.
+    pub fn method<F>(mut self, name: &str, method: F) -> Self
+    where
+        F: Fn(&mut T, &CallContext) -> DukResult<Value> + 'static,
+    {
+        self.methods.push((String::from(name), Box::new(method)));
+        self
+    }
+
+    /// Adds a method to the constructor itself.
+    pub fn static_method<F>(mut self, name: &str, method: F) -> Self
+    where
+        F: Fn(&CallContext) -> DukResult<Value> + 'static,
+    {
+        self.static_methods.push((String::from(name), Box::new(method)));
+        self
+    }
+
+    /// Adds a property to the prototype, read by calling `getter` with the Rust value of the
+    /// instance. Without a setter, assigning the property throws in strict mode.
+    pub fn getter<F>(mut self, name: &str, getter: F) -> Self
+    where
+        F: Fn(&mut T, &CallContext) -> DukResult<Value> + 'static,
+    {
+        self.accessor(name).getter = Some(Box::new(getter));
+        self
+    }
+
+    /// Adds a property to the prototype, assigned by calling `setter` with the Rust value of the
+    /// instance and the new value.
+    pub fn setter<F>(mut self, name: &str, setter: F) -> Self
+    where
+        F: Fn(&mut T, Value, &CallContext) -> DukResult<()> + 'static,
+    {
+        self.accessor(name).setter = Some(Box::new(setter));
+        self
+    }
+
+    fn accessor(&mut self, name: &str) -> &mut Accessor<T> {
+        let idx = match self.accessors.iter().position(|a| a.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.accessors.push(Accessor {
+                    name: String::from(name),
+                    getter: None,
+                    setter: None,
+                });
+                self.accessors.len() - 1
+            }
+        };
+        &mut self.accessors[idx]
+    }
+
+    /// Creates the class and makes its constructor available as a global.
+    pub fn build(self) -> DukResult<Class<T>> {
+        let ctx = self.context;
+        let name = self.name;
+        let prototype = ctx.create_object()?;
+
+        let class_name = name.clone();
+        let build = self.constructor;
+        let constructor = ctx.create_native_function(move |call| {
+            if !call.is_constructor_call() {
+                return Err(DukError::from(
+                    DukErrorCode::Type,
+                    &format!("Class constructor {} cannot be invoked without 'new'", class_name),
+                ));
+            }
+            let build = match &build {
+                Some(build) => build,
+                None => {
+                    return Err(DukError::from(
+                        DukErrorCode::Type,
+                        &format!("{} has no constructor", class_name),
+                    ))
+                }
+            };
+            let value = build(call)?;
+            let this: Object = match call.this()? {
+                Value::Object(o) => o,
+                _ => return Err(DukError::from_str("Constructor called without an instance")),
+            };
+            attach(call.context(), &this, value)?;
+            Ok(Value::Undefined)
+        })?;
+
+        for (method_name, method) in self.methods {
+            let class_name = name.clone();
+            let function = ctx.create_native_function(move |call| {
+                let this = call.this()?;
+                with_instance(&this, &class_name, |value: &mut T| method(value, call))?
+            })?;
+            prototype.set(&method_name, function)?;
+        }
+        for accessor in self.accessors {
+            let getter = match accessor.getter {
+                Some(getter) => {
+                    let class_name = name.clone();
+                    Some(ctx.create_native_function(move |call| {
+                        let this = call.this()?;
+                        with_instance(&this, &class_name, |value: &mut T| getter(value, call))?
+                    })?)
+                }
+                None => None,
+            };
+            let setter = match accessor.setter {
+                Some(setter) => {
+                    let class_name = name.clone();
+                    Some(ctx.create_native_function(move |call| {
+                        let this = call.this()?;
+                        let new_value = call.args().first().cloned().unwrap_or(Value::Undefined);
+                        with_instance(&this, &class_name, |value: &mut T| setter(value, new_value, call))??;
+                        Ok(Value::Undefined)
+                    })?)
+                }
+                None => None,
+            };
+            // Not enumerable, like the accessors of class bodies
+            ctx.define_accessor(
+                &prototype,
+                &accessor.name,
+                getter.as_ref().map(Function::as_object),
+                setter.as_ref().map(Function::as_object),
+                false,
+            )?;
+        }
+        for (method_name, method) in self.static_methods {
+            let function = ctx.create_native_function(move |call| method(call))?;
+            constructor.as_object().set(&method_name, function)?;
+        }
+
+        constructor.as_object().set("prototype", prototype.clone())?;
+        prototype.set("constructor", constructor.clone())?;
+        ctx.global_object()?.set(&name, constructor.clone())?;
+        Ok(Class {
+            name,
+            constructor,
+            prototype,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// A Rust type exposed as a JavaScript class, usually implemented with `#[derive(JsClass)]`.
+pub trait JsClass: Sized + 'static {
+    /// The name of the class, and of the global holding its constructor.
+    const NAME: &'static str;
+
+    /// Adds the properties backed by fields of the type.
+    fn define_fields(builder: ClassBuilder<'_, Self>) -> ClassBuilder<'_, Self> {
+        builder
+    }
+}
+
+/// The constructor, methods and accessors of a `JsClass`, usually implemented with
+/// `#[js_methods]` on an `impl` block.
+pub trait JsMethods: JsClass {
+    /// Adds the constructor, methods and accessors.
+    fn define_methods(builder: ClassBuilder<'_, Self>) -> ClassBuilder<'_, Self>;
+}
+
+/// What a constructor generated by `#[js_methods]` may return: the value itself, or a `Result`.
+#[doc(hidden)]
+pub trait IntoInstance<T> {
+    fn into_instance(self) -> DukResult<T>;
+}
+
+impl<T> IntoInstance<T> for T {
+    fn into_instance(self) -> DukResult<T> {
+        Ok(self)
+    }
+}
+
+impl<T, E: Into<DukError>> IntoInstance<T> for Result<T, E> {
+    fn into_instance(self) -> DukResult<T> {
+        self.map_err(Into::into)
+    }
+}
+
+/// A JavaScript class backed by the Rust type `T`, created with `Context::register_class`.
+#[derive(Debug)]
+pub struct Class<T> {
+    name: String,
+    constructor: Function,
+    prototype: Object,
+    marker: PhantomData<fn(T)>,
+}
+
+// Derived, it would require `T: Clone`
+impl<T> Clone for Class<T> {
+    fn clone(&self) -> Self {
+        Class {
+            name: self.name.clone(),
+            constructor: self.constructor.clone(),
+            prototype: self.prototype.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: 'static> Class<T> {
+    /// The constructor of the class.
+    pub fn constructor(&self) -> &Function {
+        &self.constructor
+    }
+
+    /// Wraps `value` in a new instance of the class, without running the constructor. This is how
+    /// host resources are handed to scripts.
+    pub fn instance(&self, value: T) -> DukResult<Object> {
+        let ctx = self.prototype.context();
+        let obj = ctx.create_object()?;
+        ctx.set_prototype(&obj, &self.prototype)?;
+        attach(ctx, &obj, value)?;
+        Ok(obj)
+    }
+
+    /// Calls `f` with the Rust value of an instance of the class. Fails with a `TypeError` if
+    /// `obj` is not an instance, or if its value is already borrowed by a method running further
+    /// up the stack.
+    pub fn with_instance<F, R>(&self, obj: &Object, f: F) -> DukResult<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        with_instance(&Value::Object(obj.clone()), &self.name, f)
+    }
+}
+
+impl Context {
+    /// Starts the registration of a JavaScript class named `name`, backed by the Rust type `T`.
+    ///
+    /// Every instance owns a `T`, dropped when the instance is garbage collected.
+    ///
+    /// ```ignore
+    /// ctx.register_class::<Point>("Point")
+    ///     .constructor(|call| Ok(Point { x: arg(call, 0)?, y: arg(call, 1)? }))
+    ///     .method("norm", |p, _call| Ok(Value::from((p.x * p.x + p.y * p.y).sqrt())))
+    ///     .getter("x", |p, _call| Ok(Value::from(p.x)))
+    ///     .static_method("origin", |call| { ... })
+    ///     .build()?;
+    ///
+    /// ctx.eval_string("new Point(3, 4).norm()")?; // 5
+    /// ```
+    pub fn register_class<T: 'static>(&self, name: &str) -> ClassBuilder<'_, T> {
+        ClassBuilder {
+            context: self,
+            name: String::from(name),
+            constructor: None,
+            methods: Vec::new(),
+            static_methods: Vec::new(),
+            accessors: Vec::new(),
+        }
+    }
+
+    /// Registers the class `T`, as described by its `JsClass` and `JsMethods` implementations.
+    ///
+    /// ```ignore
+    /// #[derive(JsClass)]
+    /// struct Point {
+    ///     #[js(get, set)]
+    ///     x: f64,
+    ///     #[js(get, set)]
+    ///     y: f64,
+    /// }
+    ///
+    /// #[js_methods]
+    /// impl Point {
+    ///     #[js(constructor)]
+    ///     fn new(x: f64, y: f64) -> Point {
+    ///         Point { x, y }
+    ///     }
+    ///
+    ///     fn norm(&self) -> f64 {
+    ///         (self.x * self.x + self.y * self.y).sqrt()
+    ///     }
+    /// }
+    ///
+    /// ctx.register_js_class::<Point>()?;
+    /// ctx.eval_string("new Point(3, 4).norm()")?; // 5
+    /// ```
+    pub fn register_js_class<T: JsMethods>(&self) -> DukResult<Class<T>> {
+        let builder = self.register_class::<T>(T::NAME);
+        T::define_methods(T::define_fields(builder)).build()
+    }
+}
+
+/// Gives `obj` ownership of `value`.
+fn attach<T: 'static>(ctx: &Context, obj: &Object, value: T) -> DukResult<()> {
+    let instance: Instance = Rc::new(RefCell::new(value));
+    let data = Box::into_raw(Box::new(instance));
+    let res = ctx.attach_pointer(obj, Some(finalize_instance), INSTANCE_KEY, data as *mut c_void);
+    if res.is_err() {
+        drop(unsafe { Box::from_raw(data) });
+    }
+    res
+}
+
+fn with_instance<T, F, R>(this: &Value, class_name: &str, f: F) -> DukResult<R>
+where
+    T: 'static,
+    F: FnOnce(&mut T) -> R,
+{
+    let not_an_instance = || {
+        DukError::from(
+            DukErrorCode::Type,
+            &format!("Receiver is not an instance of {}", class_name),
+        )
+    };
+    let obj = match this {
+        Value::Object(o) => o,
+        _ => return Err(not_an_instance()),
+    };
+    // Objects inheriting from an instance are not instances themselves
+    let ptr = obj.context().attached_pointer(obj, INSTANCE_KEY)? as *const Instance;
+    let instance = match unsafe { ptr.as_ref() } {
+        Some(instance) => instance.clone(),
+        None => return Err(not_an_instance()),
+    };
+    let cell = match instance.downcast_ref::<RefCell<T>>() {
+        Some(cell) => cell,
+        None => return Err(not_an_instance()),
+    };
+    let mut value = match cell.try_borrow_mut() {
+        Ok(value) => value,
+        Err(_) => {
+            return Err(DukError::from(
+                DukErrorCode::Type,
+                &format!("{} instance is already in use", class_name),
+            ))
+        }
+    };
+    Ok(f(&mut value))
+}
+
+unsafe extern "C" fn finalize_instance(ctx: *mut duk_context) -> duk_ret_t {
+    // The instance being finalized is the only argument
+    let instance = hidden_pointer(ctx, 0, INSTANCE_KEY) as *mut Instance;
+    if !instance.is_null() {
+        delete_hidden(ctx, 0, INSTANCE_KEY);
+        drop_boxed(instance);
+    }
+    0
+}
@@ -1,21 +1,35 @@
+use crate::builder::ContextBuilder;
 use crate::error::DukError;
 use crate::error::DukErrorCode;
+use crate::error::{ErrorCause, ERROR_CAUSE_KEY};
+use crate::extensions::Extensions;
+use crate::function::{hidden_pointer, put_hidden_pointer};
+use crate::heap::{Heap, HeapState};
+#[cfg(feature = "exec-timeout")]
+use crate::interrupt::InterruptHandle;
 use crate::types::Number;
 use crate::types::Value;
 use crate::DukResult;
 use anyhow;
-use dukbind::{double_t, duk_bool_t, duk_context, duk_create_heap_default, duk_del_prop, duk_destroy_heap, duk_dup, duk_eval_string, duk_get_boolean, duk_get_error_code, duk_get_heapptr, duk_get_number, duk_get_prop_lstring, duk_get_string, duk_get_type, duk_is_undefined, duk_json_decode, duk_json_encode, duk_pop, duk_pop_2, duk_push_boolean, duk_push_heap_stash, duk_push_heapptr, duk_push_lstring, duk_push_nan, duk_push_null, duk_push_number, duk_push_pointer, duk_push_undefined, duk_put_prop, duk_put_prop_lstring, duk_size_t, DUK_TYPE_BOOLEAN, DUK_TYPE_NONE, DUK_TYPE_NULL, DUK_TYPE_NUMBER, DUK_TYPE_OBJECT, DUK_TYPE_STRING, DUK_TYPE_UNDEFINED, duk_is_null, duk_is_object, duk_to_string};
+use dukbind::{double_t, duk_bool_t, duk_context, duk_del_prop, duk_dup, duk_eval_raw, duk_get_boolean, duk_get_error_code, duk_get_heapptr, duk_get_number, duk_get_prop_lstring, duk_get_lstring, duk_get_context, duk_get_type, duk_is_undefined, duk_json_decode, duk_json_encode, duk_compile_raw, duk_dump_function, duk_get_buffer_data, duk_load_function, duk_pcall, duk_pcall_method, duk_pcall_prop, duk_push_buffer_raw, duk_push_global_object, duk_pop, duk_pop_2, duk_push_boolean, duk_push_heap_stash, duk_push_heapptr, duk_push_lstring, duk_push_nan, duk_push_null, duk_push_number, duk_push_pointer, duk_push_thread_raw, duk_push_current_thread, duk_push_undefined, duk_put_prop, duk_ret_t, duk_safe_call, duk_set_prototype, duk_get_current_magic, duk_get_magic, duk_is_c_function, duk_is_constructor_call, duk_push_current_function, duk_push_this, duk_set_magic, duk_push_array, duk_push_object, duk_is_function, duk_pnew, duk_c_function, duk_errcode_t, duk_get_top, duk_push_c_function, duk_push_error_object_raw, duk_set_finalizer, DUK_VARARGS, duk_size_t, DUK_COMPILE_EVAL, DUK_COMPILE_NOFILENAME, DUK_COMPILE_NOSOURCE, DUK_COMPILE_SAFE, DUK_TYPE_BOOLEAN, DUK_TYPE_NONE, DUK_TYPE_NULL, DUK_TYPE_NUMBER, DUK_TYPE_OBJECT, DUK_TYPE_STRING, DUK_TYPE_UNDEFINED, duk_is_null, duk_is_object, duk_to_string, duk_def_prop, duk_get_prop, duk_uint_t, DUK_DEFPROP_CONFIGURABLE, DUK_DEFPROP_ENUMERABLE, DUK_DEFPROP_HAVE_CONFIGURABLE, DUK_DEFPROP_HAVE_ENUMERABLE, DUK_DEFPROP_HAVE_GETTER, DUK_DEFPROP_HAVE_SETTER};
 use std::convert::TryInto;
 use std::f64;
-use std::ffi::{CStr, CString};
-use std::mem;
-use std::os::raw::c_void;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
 use std::ptr::NonNull;
+use std::rc::Rc;
+#[cfg(feature = "exec-timeout")]
+use std::time::Duration;
+
+/// Messages of the errors duktape throws when an allocation fails, either directly or while
+/// creating the error for it.
+const ALLOC_FAILED_MESSAGES: [&str; 2] = ["alloc failed", "error in error handling"];
 
 /// Wrapper around low level API calls. Guarantees the call blocks are safe and don't leave dirt on the JS stack.
 struct CallBlock<'a> {
     stack_size: u32,
     context: &'a Context,
+    ctx: *mut duk_context,
 }
 
 impl<'a> CallBlock<'a> {
@@ -23,6 +37,7 @@ impl<'a> CallBlock<'a> {
         Self {
             stack_size: 0,
             context,
+            ctx: context.as_ptr(),
         }
     }
 
@@ -46,11 +61,12 @@ impl<'a> CallBlock<'a> {
 
     /// Gets internal context pointer.
     fn ctx_ptr(&self) -> *mut duk_context {
-        self.context.ctx.as_ptr()
+        self.ctx
     }
 
-    /// Get a DukValue from the value at the top of the value stack in the context.
-    fn get(&mut self) -> Result<Value<'a>, anyhow::Error> {
+    /// Get a DukValue from the value at the top of the value stack in the context. Fails for an
+    /// object that can't be kept alive for Rust, e.g. because the heap is out of memory.
+    fn get(&mut self) -> DukResult<Value> {
         // Make sure we have something in the stack to get
         assert!(self.stack_size > 0);
 
@@ -65,28 +81,28 @@ impl<'a> CallBlock<'a> {
             }
             DUK_TYPE_NUMBER => {
                 let v = unsafe { duk_get_number(self.ctx_ptr(), -1) };
-                if v.fract() > 0_f64 {
+                if v.is_nan() {
+                    Value::Number(Number::NaN)
+                } else if v.is_infinite() {
+                    Value::Number(Number::Infinity)
+                } else if v.fract() != 0.0 {
                     Value::Number(Number::Float(v))
                 } else {
-                    if v.is_nan() {
-                        Value::Number(Number::NaN)
-                    } else if v.is_infinite() {
-                        Value::Number(Number::Infinity)
-                    } else {
-                        Value::Number(Number::Int(v as i64))
-                    }
+                    Value::Number(Number::Int(v as i64))
                 }
             }
             DUK_TYPE_STRING => {
+                // Strings may contain NULs, read them by length
                 let v = unsafe {
-                    let v = duk_get_string(self.ctx_ptr(), -1);
-                    CStr::from_ptr(v)
+                    let mut len: duk_size_t = 0;
+                    let v = duk_get_lstring(self.ctx_ptr(), -1, &mut len);
+                    std::slice::from_raw_parts(v as *const u8, len)
                 };
-                let cow = v.to_string_lossy();
+                let cow = String::from_utf8_lossy(v);
                 Value::String(String::from(cow))
             }
             DUK_TYPE_OBJECT => {
-                let obj = Object::new(self).unwrap();
+                let obj = Object::new(self)?;
                 Value::Object(obj)
             }
             _ => Value::Undefined,
@@ -94,47 +110,50 @@ impl<'a> CallBlock<'a> {
         Ok(res)
     }
 
-    fn push_lstring(&mut self, string: &str) {
-        let s = String::from(string);
-        unsafe {
-            duk_push_lstring(
-                self.ctx_ptr(),
-                s.as_ptr() as *const i8,
-                s.len() as duk_size_t,
-            );
+    /// Pushes `string`, or returns the error raised while allocating it, leaving nothing on the
+    /// stack then.
+    fn push_lstring(&mut self, string: &str) -> DukResult<()> {
+        unsafe extern "C" fn push(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            let string = &*(udata as *const &str);
+            duk_push_lstring(ctx, string.as_ptr() as *const c_char, string.len() as duk_size_t);
+            1
         }
-        self.inc();
-    }
 
-    pub fn json_decode(&self, idx: i32) -> Result<(), anyhow::Error> {
-        // referenced value needs to be in the stack
-        self.validate_stack_idx(idx)?;
-        unsafe {
-            duk_json_decode(self.ctx_ptr(), idx);
-        }
-        Ok(())
+        self.push_safe(push, &string as *const &str as *mut c_void)
     }
 
-    pub fn json_encode(&self, idx: i32) -> Result<String, anyhow::Error> {
-        self.validate_stack_idx(idx)?;
-        let t = unsafe {
-            let raw = duk_json_encode(self.ctx_ptr(), idx);
-            if raw.is_null() {
-                return Err(anyhow::anyhow!("Could not encode value as string"));
-            }
-            CStr::from_ptr(raw)
-        };
-        Ok(String::from(t.to_string_lossy()))
+    /// Pushes the value returned by `func`, run in protected mode. If it throws, e.g. because the
+    /// heap is out of memory, nothing is left on the stack and the error is returned instead.
+    fn push_safe(
+        &mut self,
+        func: unsafe extern "C" fn(*mut duk_context, *mut c_void) -> duk_ret_t,
+        udata: *mut c_void,
+    ) -> DukResult<()> {
+        if self.safe_call(func, udata, 0) == 0 {
+            return Ok(());
+        }
+        let err = self.error();
+        self.pop();
+        Err(err)
     }
 
-    fn eval_string(&mut self, code: &str) -> u32 {
-        // TODO: this method should return Result type
+    /// Evaluates `code` in protected mode, leaving the result or the error on the stack. The source
+    /// is passed along with its length, so it doesn't need to be NUL-terminated and may contain NULs.
+    fn eval_string(&mut self, code: &[u8]) -> i32 {
         self.inc();
-        unsafe { duk_eval_string(self.context.ctx.as_ptr(), code) }
+        let flags = DUK_COMPILE_EVAL | DUK_COMPILE_SAFE | DUK_COMPILE_NOSOURCE | DUK_COMPILE_NOFILENAME;
+        unsafe {
+            duk_eval_raw(
+                self.ctx_ptr(),
+                code.as_ptr() as *const c_char,
+                code.len() as duk_size_t,
+                flags,
+            )
+        }
     }
 
     fn get_error_code(&self) -> u32 {
-        unsafe { duk_get_error_code(self.context.ctx.as_ptr(), -1) as u32 }
+        unsafe { duk_get_error_code(self.ctx_ptr(), -1) as u32 }
     }
 
     pub fn is_undefined(&self, idx: i32) -> Result<bool, anyhow::Error> {
@@ -180,42 +199,11 @@ impl<'a> CallBlock<'a> {
         Ok(heap)
     }
 
-    pub fn push_pointer(&mut self, ptr: NonNull<c_void>) {
-        self.inc();
-        unsafe { duk_push_pointer(self.ctx_ptr(), ptr.as_ptr()) };
-    }
-
     pub fn push_heap_stash(&mut self) {
         self.inc();
         unsafe { duk_push_heap_stash(self.ctx_ptr()) };
     }
 
-    pub fn put_prop(&mut self, idx: i32) -> Result<(), anyhow::Error> {
-        self.validate_stack_idx(idx)?;
-        let res = unsafe { duk_put_prop(self.ctx_ptr(), idx) };
-        self.dec();
-        self.dec();
-        if res == 1 {
-            Ok(())
-        } else {
-            // TODO: catch error from JS
-            Err(anyhow::anyhow!("JS error thrown while trying to set prop"))
-        }
-    }
-
-    fn get_prop_lstring(&self, idx: i32, name: &str) -> i32 {
-        // referenced value needs to be in the stack
-        assert!(self.stack_size >= i32::abs(idx) as u32);
-        unsafe {
-            duk_get_prop_lstring(
-                self.ctx_ptr(),
-                idx,
-                name.as_ptr() as *const i8,
-                name.len() as duk_size_t,
-            ) as i32
-        }
-    }
-
     fn push_heapptr(&mut self, heap: &NonNull<c_void>) -> i32 {
         self.inc();
         unsafe { duk_push_heapptr(self.ctx_ptr(), heap.as_ptr()) }
@@ -246,25 +234,304 @@ impl<'a> CallBlock<'a> {
         unsafe { duk_push_boolean(self.ctx_ptr(), val as duk_bool_t) }
     }
 
-    pub fn put_prop_lstring(&mut self, obj_idx: i32, prop_name: &str) -> DukResult<()> {
-        // referenced value needs to be in the stack
-        assert!(self.stack_size >= i32::abs(obj_idx) as u32);
-        self.dec();
-        let key = CString::new(prop_name).unwrap();
-        let key_len = prop_name.len() as duk_size_t;
-        let result = unsafe {
-            duk_put_prop_lstring(self.ctx_ptr(), obj_idx, key.as_ptr(), key_len)
+    /// Pushes a Rust side value to the stack.
+    fn push_value(&mut self, value: &Value) -> DukResult<()> {
+        match value {
+            Value::Undefined => self.push_undefined(),
+            Value::Null => self.push_null(),
+            Value::Number(n) => {
+                if let Number::NaN = n {
+                    self.push_nan();
+                } else if let Number::Infinity = n {
+                    self.push_number(f64::INFINITY);
+                } else {
+                    self.push_number(f64::from(n.clone()));
+                }
+            }
+            Value::Boolean(b) => self.push_boolean(*b),
+            Value::String(s) => self.push_lstring(s.as_str())?,
+            Value::Object(o) => {
+                if !o.context.ptr_eq(self.context) {
+                    return Err(DukError::from(
+                        DukErrorCode::Error,
+                        "Cannot use an object from another context.",
+                    ));
+                }
+                self.push_heapptr(&o.heap);
+                if self.is_undefined(-1).unwrap() {
+                    return Err(DukError::from(
+                        DukErrorCode::Error,
+                        "Error setting property to undefined object.",
+                    ));
+                }
+            }
+        };
+        Ok(())
+    }
+
+    /// Calls the function below `nargs` arguments in protected mode, leaving the result or the error in its place.
+    fn pcall(&mut self, nargs: i32) -> i32 {
+        assert!(self.stack_size > nargs as u32);
+        let res = unsafe { duk_pcall(self.ctx_ptr(), nargs) };
+        self.stack_size -= nargs as u32;
+        res
+    }
+
+    /// Builds a DukError out of the error value at the top of the stack.
+    fn error(&mut self) -> DukError {
+        let state = &self.context.heap.state;
+        if !self.is_object(-1).unwrap() {
+            // Not an Error instance, use the thrown value itself
+            let code = state.error_code(self.get_error_code(), false);
+            let message = self.get().unwrap_or(Value::Undefined).to_string();
+            return DukError::from(code, message.as_ref());
+        }
+
+        let alloc_failed = match self.error_property("message") {
+            Value::String(s) => ALLOC_FAILED_MESSAGES.contains(&s.as_str()),
+            _ => false,
+        };
+        let code = state.error_code(self.get_error_code(), alloc_failed);
+
+        let file_name = match self.error_property("fileName") {
+            Value::String(s) => Some(s),
+            _ => None,
+        };
+        let line_number = match self.error_property("lineNumber") {
+            Value::Number(n) => Some(i64::from(n) as u32),
+            _ => None,
+        };
+        let cause = self.error_cause();
+
+        let message = match self.error_property("stack") {
+            Value::Undefined => self.error_json(),
+            stack => stack.to_string(),
+        };
+        DukError::from(code, message.as_ref())
+            .with_location(file_name, line_number)
+            .with_cause(cause)
+    }
+
+    /// The property `name` of the error object at the top of the stack, read in protected mode.
+    /// A property that can't be read, e.g. because its getter throws, counts as `undefined`.
+    fn error_property(&mut self, name: &str) -> Value {
+        unsafe extern "C" fn get(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            let name = &*(udata as *const &str);
+            duk_get_prop_lstring(ctx, 0, name.as_ptr() as *const c_char, name.len() as duk_size_t);
+            1
+        }
+
+        self.dup(-1).unwrap();
+        let value = match self.safe_call(get, &name as *const &str as *mut c_void, 1) {
+            0 => self.get().unwrap_or(Value::Undefined),
+            _ => Value::Undefined,
+        };
+        self.pop();
+        value
+    }
+
+    /// The JSON text of the error object at the top of the stack, as `Value::to_string` gives for
+    /// objects, without keeping the error alive for Rust.
+    fn error_json(&mut self) -> String {
+        self.dup(-1).unwrap();
+        let json = match self.json_encode_safe() {
+            0 => self.get().ok(),
+            _ => None,
+        };
+        self.pop();
+        match json {
+            Some(Value::String(json)) => json,
+            _ => String::from("{}"),
+        }
+    }
+
+    /// The Rust error a native function attached to the error object at the top of the stack.
+    /// Objects inheriting from such an error don't carry its cause.
+    fn error_cause(&mut self) -> Option<ErrorCause> {
+        unsafe {
+            let ptr = hidden_pointer(self.ctx_ptr(), -1, ERROR_CAUSE_KEY) as *const ErrorCause;
+            ptr.as_ref().cloned()
+        }
+    }
+
+    /// Replaces the compiled function at the top of the stack with its bytecode, returning a copy of
+    /// it, or the error raised while dumping it.
+    fn dump_function(&mut self) -> DukResult<Vec<u8>> {
+        unsafe extern "C" fn dump(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_dump_function(ctx);
+            1
+        }
+
+        if self.safe_call(dump, std::ptr::null_mut(), 1) != 0 {
+            return Err(self.error());
+        }
+        unsafe {
+            let mut size: duk_size_t = 0;
+            let data = duk_get_buffer_data(self.ctx_ptr(), -1, &mut size);
+            if data.is_null() {
+                return Ok(Vec::new());
+            }
+            Ok(std::slice::from_raw_parts(data as *const u8, size as usize).to_vec())
+        }
+    }
+
+    /// Pushes the function loaded from `bytecode`, or the error raised while loading it.
+    fn load_function(&mut self, bytecode: &[u8]) -> i32 {
+        unsafe extern "C" fn load(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            let bytecode = &*(udata as *const &[u8]);
+            let data = duk_push_buffer_raw(ctx, bytecode.len() as duk_size_t, 0);
+            if !bytecode.is_empty() {
+                std::ptr::copy_nonoverlapping(bytecode.as_ptr(), data as *mut u8, bytecode.len());
+            }
+            duk_load_function(ctx);
+            1
+        }
+
+        self.safe_call(load, &bytecode as *const &[u8] as *mut c_void, 0)
+    }
+
+    /// Runs `func` in protected mode with the `nargs` values on top of the stack as arguments. They
+    /// get replaced by its return value, or by the error it threw.
+    ///
+    /// An error thrown by `func` unwinds it with longjmp, it must not own anything needing a drop.
+    fn safe_call(
+        &mut self,
+        func: unsafe extern "C" fn(*mut duk_context, *mut c_void) -> duk_ret_t,
+        udata: *mut c_void,
+        nargs: u32,
+    ) -> i32 {
+        assert!(self.stack_size >= nargs);
+        self.stack_size = self.stack_size - nargs + 1;
+        unsafe { duk_safe_call(self.ctx_ptr(), Some(func), udata, nargs as i32, 1) }
+    }
+
+    /// Defines a property of the object below the `nargs - 1` values on top of the stack (its key
+    /// and attributes, as expected by `duk_def_prop` with `flags`), in protected mode. They get
+    /// replaced by the error raised while doing so, or `undefined`.
+    fn def_prop(&mut self, nargs: u32, flags: u32) -> i32 {
+        unsafe extern "C" fn define(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            duk_def_prop(ctx, 0, udata as usize as duk_uint_t);
+            0
+        }
+
+        self.safe_call(define, flags as usize as *mut c_void, nargs)
+    }
+
+    /// Replaces the object and key on top of the stack with the value of the property, read in
+    /// protected mode. Returns whether the property exists, or the error thrown while reading it,
+    /// e.g. by a getter, which is left on the stack in place of the value.
+    fn get_prop_safe(&mut self) -> DukResult<bool> {
+        let mut found: duk_bool_t = 0;
+        if self.get_prop_raw(&mut found) == 0 {
+            Ok(found == 1)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Like `get_prop_safe`, returning the result of the protected call and storing whether the
+    /// property exists in `found`.
+    fn get_prop_raw(&mut self, found: &mut duk_bool_t) -> i32 {
+        unsafe extern "C" fn get(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            *(udata as *mut duk_bool_t) = duk_get_prop(ctx, 0);
+            1
+        }
+
+        self.safe_call(get, found as *mut duk_bool_t as *mut c_void, 2)
+    }
+
+    /// Assigns the value on top of the stack to the property below it of the object below them,
+    /// in protected mode. They get replaced by the error thrown while doing so, or `undefined`.
+    /// Assignments failing silently in non strict code, e.g. to a read only property, throw.
+    fn put_prop_safe(&mut self) -> i32 {
+        unsafe extern "C" fn put(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            // Native functions are strict, a failed assignment throws a TypeError
+            duk_put_prop(ctx, 0);
+            0
+        }
+
+        self.safe_call(put, std::ptr::null_mut(), 3)
+    }
+
+    /// Replaces the JSON text on top of the stack with the decoded value, or the syntax error.
+    fn json_decode_safe(&mut self) -> i32 {
+        unsafe extern "C" fn decode(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_json_decode(ctx, 0);
+            1
+        }
+
+        self.safe_call(decode, std::ptr::null_mut(), 1)
+    }
+
+    /// Replaces the value on top of the stack with its JSON text, `undefined` if it has none, or
+    /// the error thrown while encoding it, e.g. by a `toJSON` method or for a cyclic structure.
+    fn json_encode_safe(&mut self) -> i32 {
+        unsafe extern "C" fn encode(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_json_encode(ctx, 0);
+            1
+        }
+
+        self.safe_call(encode, std::ptr::null_mut(), 1)
+    }
+
+    fn push_global_object(&mut self) {
+        self.inc();
+        unsafe { duk_push_global_object(self.ctx_ptr()) };
+    }
+
+    /// Compiles `source` in protected mode, pushing the compiled function or returning the error.
+    fn compile(&mut self, source: &[u8], filename: &str, flags: u32) -> DukResult<()> {
+        self.push_lstring(filename)?;
+        // The filename is the only argument, it gets replaced by the result
+        let flags = flags | 1 | DUK_COMPILE_SAFE | DUK_COMPILE_NOSOURCE;
+        let res = unsafe {
+            duk_compile_raw(
+                self.ctx_ptr(),
+                source.as_ptr() as *const c_char,
+                source.len() as duk_size_t,
+                flags,
+            )
         };
-        if result == 1 {
+        if res == 0 {
             Ok(())
         } else {
-            Err(DukError::from(
-                DukErrorCode::Error,
-                "Failed to set property.",
-            ))
+            Err(self.error())
         }
     }
 
+    /// Calls the function below `this` and `nargs` arguments in protected mode, leaving the result
+    /// or the error in its place.
+    fn pcall_method(&mut self, nargs: i32) -> i32 {
+        assert!(self.stack_size > nargs as u32 + 1);
+        let res = unsafe { duk_pcall_method(self.ctx_ptr(), nargs) };
+        self.stack_size -= nargs as u32 + 1;
+        res
+    }
+
+    /// Calls the method of the object at `obj_idx` named by the key below `nargs` arguments, in
+    /// protected mode. Reading the method is protected too, e.g. from a throwing getter. The key
+    /// and arguments get replaced by the return value or the error.
+    fn pcall_prop(&mut self, obj_idx: i32, nargs: i32) -> i32 {
+        assert!(self.stack_size > nargs as u32 + 1);
+        let res = unsafe { duk_pcall_prop(self.ctx_ptr(), obj_idx, nargs) };
+        self.stack_size -= nargs as u32;
+        res
+    }
+
+    /// Calls the constructor below `nargs` arguments in protected mode, leaving the new object or
+    /// the error in its place.
+    fn pnew(&mut self, nargs: i32) -> i32 {
+        assert!(self.stack_size > nargs as u32);
+        let res = unsafe { duk_pnew(self.ctx_ptr(), nargs) };
+        self.stack_size -= nargs as u32;
+        res
+    }
+
+    fn is_function(&self, idx: i32) -> Result<bool, anyhow::Error> {
+        self.validate_stack_idx(idx)?;
+        Ok(unsafe { duk_is_function(self.ctx_ptr(), idx) } == 1)
+    }
+
     pub fn dup(&mut self, idx: i32) -> Result<(), anyhow::Error> {
         self.validate_stack_idx(idx).map(|_| {
             self.inc();
@@ -272,6 +539,15 @@ impl<'a> CallBlock<'a> {
         })
     }
 
+    fn push_thread(&mut self) -> DukResult<()> {
+        unsafe extern "C" fn push(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_push_thread_raw(ctx, 0);
+            1
+        }
+
+        self.push_safe(push, std::ptr::null_mut())
+    }
+
     fn pop(&mut self) {
         // Make sure we have something in the stack to pop
         assert!(self.stack_size > 0);
@@ -291,98 +567,570 @@ impl<'a> Drop for CallBlock<'a> {
     }
 }
 
+/// The arguments of the protected calls attaching a pointer to an object. Plain data, since an
+/// error unwinds the call with longjmp.
+struct PointerArgs<'a> {
+    func: duk_c_function,
+    finalizer: duk_c_function,
+    key: &'a [u8],
+    data: *mut c_void,
+}
+
 /// Wrapper around a duktape context. Usable for evaluating and returning values from the context that can be used in Rust.
+///
+/// A `Context` is a reference-counted handle: clones refer to the same duktape heap.
 #[derive(Clone, Debug)]
 pub struct Context {
     ctx: NonNull<duk_context>,
+    heap: Rc<Heap>,
+    /// The duktape thread object backing this context, if it was spawned from another one.
+    thread: Option<Rc<Object>>,
 }
 
 impl Context {
     /// Create a duktape context.
     pub fn new() -> anyhow::Result<Context> {
-        let ctx = unsafe { NonNull::new(duk_create_heap_default()) };
-        match ctx {
-            Some(ctx) => Ok(Self { ctx }),
-            None => Err(anyhow::anyhow!("Could not create context")),
+        ContextBuilder::new().build()
+    }
+
+    /// Wraps a freshly created heap.
+    pub(crate) fn from_heap(heap: Heap) -> Context {
+        let heap = Rc::new(heap);
+        *heap.state.heap.borrow_mut() = Rc::downgrade(&heap);
+        Self {
+            ctx: heap.ctx,
+            heap,
+            thread: None,
         }
     }
 
-    /// Decode a JSON string into the context, returning a DukObject.
-    pub fn decode_json(&self, json: &str) -> Value {
+    /// Gets a handle to the heap `ctx` belongs to, from within a native function. Returns `None`
+    /// while the heap is being destroyed.
+    ///
+    /// The handle refers to the heap's root context rather than `ctx`, which may be a coroutine
+    /// that finishes and gets collected while objects created through the handle are still alive.
+    ///
+    /// # Safety
+    /// `ctx` must be a live context of a heap created by `ContextBuilder`.
+    pub(crate) unsafe fn from_raw(ctx: *mut duk_context) -> Option<Context> {
+        let heap = HeapState::from_ctx(ctx).heap.borrow().upgrade()?;
+        Some(Self {
+            ctx: heap.ctx,
+            heap,
+            thread: None,
+        })
+    }
+
+    /// Spawns a duktape thread. The returned context shares the heap and global object of this
+    /// one, but has its own value and call stacks. It keeps the heap alive like any other handle.
+    pub fn spawn_thread(&self) -> DukResult<Context> {
         let mut cb = CallBlock::from(self);
-        cb.push_lstring(json);
-        // We unwrap here because it's a library bug if it fails
-        cb.json_decode(-1).unwrap();
-        cb.get().unwrap()
+        cb.push_thread()?;
+        let ctx = unsafe { NonNull::new(duk_get_context(cb.ctx_ptr(), -1)) };
+        let ctx = match ctx {
+            Some(ctx) => ctx,
+            None => return Err(DukError::from_str("Could not create thread")),
+        };
+        let thread = Object::new(&mut cb)?;
+        Ok(Context {
+            ctx,
+            heap: self.heap.clone(),
+            thread: Some(Rc::new(thread)),
+        })
     }
 
-    /// Evaluate a string, returning the resulting value.
-    pub fn eval_string(&self, code: &str) -> DukResult<Value> {
+    /// Returns `true` if this context was created by `spawn_thread`.
+    pub fn is_thread(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// The heap user data pointer set with `ContextBuilder::user_data`, null if none was set.
+    pub fn user_data(&self) -> *mut c_void {
+        self.heap.state.user_data
+    }
+
+    /// Raw pointer to the duktape context. For the root context this is the currently running
+    /// thread, if any: duktape only accepts calls on the running thread or an inactive one.
+    pub(crate) fn as_ptr(&self) -> *mut duk_context {
+        let ctx = self.ctx.as_ptr();
+        if self.thread.is_some() {
+            return ctx;
+        }
+        unsafe {
+            duk_push_current_thread(ctx);
+            let running = duk_get_context(ctx, -1);
+            duk_pop(ctx);
+            if running.is_null() {
+                ctx
+            } else {
+                running
+            }
+        }
+    }
+
+    /// Host side state of the heap.
+    pub(crate) fn state(&self) -> &HeapState {
+        &self.heap.state
+    }
+
+    /// Number of handles to the heap, counting the ones held by objects.
+    pub(crate) fn handle_count(&self) -> usize {
+        Rc::strong_count(&self.heap)
+    }
+
+    /// Host state stored alongside the heap, shared by every handle to it.
+    pub fn extensions(&self) -> &Extensions {
+        &self.heap.state.extensions
+    }
+
+    /// Returns `true` if both handles refer to the same duktape heap.
+    pub fn ptr_eq(&self, other: &Context) -> bool {
+        Rc::ptr_eq(&self.heap, &other.heap)
+    }
+
+    /// Calls an internal helper function with `args`. The helper is compiled from `source` the first
+    /// time and cached in the heap stash under `name`.
+    pub(crate) fn call_helper(&self, name: &str, source: &str, args: &[&Value]) -> DukResult<Value> {
+        let _exec = self.heap.state.enter();
         let mut cb = CallBlock::from(self);
-        if cb.eval_string(code) == 0 {
-            Ok(cb.get().unwrap())
+        cb.push_heap_stash();
+        cb.push_lstring(name)?;
+        if !cb.get_prop_safe()? {
+            cb.pop();
+            if cb.eval_string(source.as_bytes()) != 0 {
+                return Err(cb.error());
+            }
+            cb.push_heap_stash();
+            cb.push_lstring(name)?;
+            cb.dup(-3).unwrap();
+            if cb.put_prop_safe() != 0 {
+                return Err(cb.error());
+            }
+            cb.pop();
+        }
+        for arg in args {
+            cb.push_value(arg)?;
+        }
+        if cb.pcall(args.len() as i32) == 0 {
+            cb.get()
         } else {
-            let code = cb.get_error_code();
-            cb.get_prop_lstring(-1, "stack");
-            let val = cb.get().unwrap();
-            let val: String = val.try_into()?;
-            let c: DukErrorCode = unsafe { mem::transmute(code) };
-            Err(DukError::from(c, val.as_ref()))
+            Err(cb.error())
         }
     }
-}
 
-impl Drop for Context {
-    fn drop(&mut self) {
-        let raw_ctx = self.ctx.as_ptr();
+    /// Compiles `source` with the given `DUK_COMPILE_*` flags, returning the compiled function.
+    pub(crate) fn compile_function(&self, source: &[u8], filename: &str, flags: u32) -> DukResult<Object> {
+        let mut cb = CallBlock::from(self);
+        cb.compile(source, filename, flags)?;
+        Object::new(&mut cb)
+    }
+
+    /// Serializes a compiled function to bytecode.
+    pub(crate) fn dump_function(&self, func: &Object) -> DukResult<Vec<u8>> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(func.clone()))?;
+        cb.dump_function()
+    }
+
+    /// Loads a function from bytecode produced by `dump_function`.
+    pub(crate) fn load_function(&self, bytecode: &[u8]) -> DukResult<Object> {
+        let mut cb = CallBlock::from(self);
+        if cb.load_function(bytecode) == 0 {
+            Object::new(&mut cb)
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Calls `func` with `args`. The `this` binding is the global object if `this` is `None`.
+    pub(crate) fn call_function(&self, func: &Object, this: Option<&Value>, args: &[&Value]) -> DukResult<Value> {
+        let _exec = self.heap.state.enter();
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(func.clone()))?;
+        match this {
+            Some(this) => cb.push_value(this)?,
+            None => cb.push_global_object(),
+        }
+        for arg in args {
+            cb.push_value(arg)?;
+        }
+        if cb.pcall_method(args.len() as i32) == 0 {
+            cb.get()
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Creates an empty object.
+    pub fn create_object(&self) -> DukResult<Object> {
+        unsafe extern "C" fn push(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_push_object(ctx);
+            1
+        }
+
+        let mut cb = CallBlock::from(self);
+        cb.push_safe(push, std::ptr::null_mut())?;
+        Object::new(&mut cb)
+    }
+
+    /// Creates an empty array.
+    pub fn create_array(&self) -> DukResult<Object> {
+        unsafe extern "C" fn push(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+            duk_push_array(ctx);
+            1
+        }
+
+        let mut cb = CallBlock::from(self);
+        cb.push_safe(push, std::ptr::null_mut())?;
+        Object::new(&mut cb)
+    }
+
+    /// Returns the global object.
+    pub(crate) fn global_object(&self) -> DukResult<Object> {
+        let mut cb = CallBlock::from(self);
+        cb.push_global_object();
+        Object::new(&mut cb)
+    }
+
+    /// Pushes a native function calling `func`, which finds `data` in the hidden property `key` of
+    /// the function object. `finalizer` is run when the function is garbage collected.
+    pub(crate) fn push_native_function(
+        &self,
+        func: duk_c_function,
+        finalizer: duk_c_function,
+        key: &[u8],
+        data: *mut c_void,
+    ) -> DukResult<Object> {
+        unsafe extern "C" fn push(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            let args = &*(udata as *const PointerArgs);
+            duk_push_c_function(ctx, args.func, DUK_VARARGS);
+            duk_push_c_function(ctx, args.finalizer, 1);
+            duk_set_finalizer(ctx, -2);
+            put_hidden_pointer(ctx, -1, args.key, args.data);
+            1
+        }
+
+        let mut cb = CallBlock::from(self);
+        let mut args = PointerArgs { func, finalizer, key, data };
+        if cb.safe_call(push, &mut args as *mut PointerArgs as *mut c_void, 0) != 0 {
+            return Err(cb.error());
+        }
+        Object::new(&mut cb)
+    }
+
+    /// Stores `data` in the hidden property `key` of `obj`, with `finalizer` run when the object
+    /// is garbage collected.
+    pub(crate) fn attach_pointer(
+        &self,
+        obj: &Object,
+        finalizer: duk_c_function,
+        key: &[u8],
+        data: *mut c_void,
+    ) -> DukResult<()> {
+        unsafe extern "C" fn attach(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            let args = &*(udata as *const PointerArgs);
+            duk_push_c_function(ctx, args.finalizer, 1);
+            duk_set_finalizer(ctx, 0);
+            put_hidden_pointer(ctx, 0, args.key, args.data);
+            0
+        }
+
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(obj.clone()))?;
+        let mut args = PointerArgs { func: None, finalizer, key, data };
+        if cb.safe_call(attach, &mut args as *mut PointerArgs as *mut c_void, 1) != 0 {
+            return Err(cb.error());
+        }
+        Ok(())
+    }
+
+    /// Reads the pointer in the hidden property `key` of `obj`, null if there is none or if it's
+    /// inherited.
+    pub(crate) fn attached_pointer(&self, obj: &Object, key: &[u8]) -> DukResult<*mut c_void> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(obj.clone()))?;
+        Ok(unsafe { hidden_pointer(cb.ctx_ptr(), -1, key) })
+    }
+
+    /// Sets the prototype of `obj`.
+    pub(crate) fn set_prototype(&self, obj: &Object, proto: &Object) -> DukResult<()> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(obj.clone()))?;
+        cb.push_value(&Value::Object(proto.clone()))?;
+        unsafe { duk_set_prototype(cb.ctx_ptr(), -2) };
+        cb.dec();
+        Ok(())
+    }
+
+    /// Defines the configurable accessor property `name` of `obj`. A missing getter or setter is
+    /// left as it was.
+    pub(crate) fn define_accessor(
+        &self,
+        obj: &Object,
+        name: &str,
+        getter: Option<&Object>,
+        setter: Option<&Object>,
+        enumerable: bool,
+    ) -> DukResult<()> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(obj.clone()))?;
+        cb.push_lstring(name)?;
+        let mut nargs = 2;
+        let mut flags = DUK_DEFPROP_HAVE_CONFIGURABLE | DUK_DEFPROP_CONFIGURABLE | DUK_DEFPROP_HAVE_ENUMERABLE;
+        if enumerable {
+            flags |= DUK_DEFPROP_ENUMERABLE;
+        }
+        if let Some(getter) = getter {
+            cb.push_value(&Value::Object(getter.clone()))?;
+            nargs += 1;
+            flags |= DUK_DEFPROP_HAVE_GETTER;
+        }
+        if let Some(setter) = setter {
+            cb.push_value(&Value::Object(setter.clone()))?;
+            nargs += 1;
+            flags |= DUK_DEFPROP_HAVE_SETTER;
+        }
+        if cb.def_prop(nargs, flags) == 0 {
+            Ok(())
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Reads the arguments of the running native function.
+    pub(crate) fn native_args(&self) -> DukResult<Vec<Value>> {
+        let mut cb = CallBlock::from(self);
+        let nargs = unsafe { duk_get_top(cb.ctx_ptr()) };
+        (0..nargs)
+            .map(|idx| {
+                cb.inc();
+                unsafe { duk_dup(cb.ctx_ptr(), idx) };
+                let value = cb.get();
+                cb.pop();
+                value
+            })
+            .collect()
+    }
+
+    /// The `this` binding of the running native function.
+    pub(crate) fn native_this(&self) -> DukResult<Value> {
+        let mut cb = CallBlock::from(self);
+        cb.inc();
+        unsafe { duk_push_this(cb.ctx_ptr()) };
+        cb.get()
+    }
+
+    /// The running native function itself.
+    pub(crate) fn native_callee(&self) -> DukResult<Object> {
+        let mut cb = CallBlock::from(self);
+        cb.inc();
+        unsafe { duk_push_current_function(cb.ctx_ptr()) };
+        Object::new(&mut cb)
+    }
+
+    /// Returns `true` if the running native function was called with `new`.
+    pub(crate) fn native_is_constructor_call(&self) -> bool {
+        unsafe { duk_is_constructor_call(self.as_ptr()) == 1 }
+    }
+
+    /// The magic value of the running native function.
+    pub(crate) fn native_magic(&self) -> i32 {
+        unsafe { duk_get_current_magic(self.as_ptr()) }
+    }
+
+    /// Sets the magic value of a native function.
+    pub(crate) fn set_magic(&self, func: &Object, magic: i32) -> DukResult<()> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(func.clone()))?;
+        unsafe {
+            // Duktape throws for other functions
+            if duk_is_c_function(cb.ctx_ptr(), -1) == 0 {
+                return Err(DukError::from(DukErrorCode::Type, "Not a native function"));
+            }
+            duk_set_magic(cb.ctx_ptr(), -1, magic);
+        }
+        Ok(())
+    }
+
+    /// The magic value of a native function, 0 for other functions.
+    pub(crate) fn magic(&self, func: &Object) -> DukResult<i32> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(func.clone()))?;
         unsafe {
-            duk_destroy_heap(raw_ctx);
+            if duk_is_c_function(cb.ctx_ptr(), -1) == 0 {
+                return Ok(0);
+            }
+            Ok(duk_get_magic(cb.ctx_ptr(), -1))
         }
     }
+
+    /// Pushes the return value of a native function, leaving it on the stack.
+    pub(crate) fn push_native_result(&self, value: &Value) -> DukResult<()> {
+        let mut cb = CallBlock::from(self);
+        cb.push_value(value)?;
+        cb.dec();
+        Ok(())
+    }
+
+    /// Returns `true` if `obj` is callable.
+    pub(crate) fn is_function(&self, obj: &Object) -> bool {
+        let mut cb = CallBlock::from(self);
+        if cb.push_value(&Value::Object(obj.clone())).is_err() {
+            return false;
+        }
+        cb.is_function(-1).unwrap()
+    }
+
+    /// Calls `func` as a constructor with `args`, as `new func(...args)` would.
+    pub(crate) fn construct_function(&self, func: &Object, args: &[&Value]) -> DukResult<Value> {
+        let _exec = self.heap.state.enter();
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(func.clone()))?;
+        for arg in args {
+            cb.push_value(arg)?;
+        }
+        if cb.pnew(args.len() as i32) == 0 {
+            cb.get()
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Calls the method `name` of `obj` with `args`, `obj` being the `this` binding.
+    pub(crate) fn call_method(&self, obj: &Object, name: &str, args: &[&Value]) -> DukResult<Value> {
+        let _exec = self.heap.state.enter();
+        let mut cb = CallBlock::from(self);
+        cb.push_value(&Value::Object(obj.clone()))?;
+        cb.push_lstring(name)?;
+        for arg in args {
+            cb.push_value(arg)?;
+        }
+        if cb.pcall_prop(-(args.len() as i32) - 2, args.len() as i32) == 0 {
+            cb.get()
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Decode a JSON string into the context, returning a DukObject.
+    pub fn decode_json(&self, json: &str) -> DukResult<Value> {
+        let mut cb = CallBlock::from(self);
+        cb.push_lstring(json)?;
+        if cb.json_decode_safe() == 0 {
+            cb.get()
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Evaluate a string, returning the resulting value. The code can be given as `&str` or as raw
+    /// bytes, which duktape reads as CESU-8. NUL characters are kept as is.
+    pub fn eval_string<S: AsRef<[u8]>>(&self, code: S) -> DukResult<Value> {
+        let state = &self.heap.state;
+        let _exec = state.enter();
+        let mut cb = CallBlock::from(self);
+        if cb.eval_string(code.as_ref()) == 0 {
+            cb.get()
+        } else {
+            Err(cb.error())
+        }
+    }
+
+    /// Changes the memory limit of the heap, `None` removes it. Memory already in use is not
+    /// reclaimed if it exceeds the new limit, further allocations are refused instead.
+    pub fn set_memory_limit(&self, bytes: Option<usize>) {
+        self.heap.state.memory.limit.set(bytes);
+    }
+
+    /// Current memory limit of the heap, if any.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.heap.state.memory.limit.get()
+    }
+
+    /// Bytes currently allocated by the heap.
+    pub fn memory_usage(&self) -> usize {
+        self.heap.state.memory.used.get()
+    }
+
+    /// Highest number of bytes allocated by the heap at once.
+    pub fn peak_memory_usage(&self) -> usize {
+        self.heap.state.memory.peak.get()
+    }
+
+    /// Changes how long each script execution can run, `None` removes the limit.
+    #[cfg(feature = "exec-timeout")]
+    pub fn set_time_limit(&self, limit: Option<Duration>) {
+        self.heap.state.exec.time_limit.set(limit);
+    }
+
+    /// Current time limit of script executions, if any.
+    #[cfg(feature = "exec-timeout")]
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.heap.state.exec.time_limit.get()
+    }
+
+    /// Returns a handle that can be sent to other threads to abort the script running in this context.
+    #[cfg(feature = "exec-timeout")]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.heap.state.exec.interrupt_handle()
+    }
 }
 
 /// A wrapper around duktape's heapptr. These represent JavaScript objects.
+///
+/// An object holds its own `Context` handle, keeping the heap alive for as long as the object exists.
 #[derive(Debug)]
-pub struct Object<'a> {
-    context: &'a Context,
+pub struct Object {
+    context: Context,
     heap: NonNull<c_void>,
 }
 
-impl<'a> Object<'a> {
-    /// Creates a new DukObject from the object at the top of the value stack.
-    fn new(cb: &mut CallBlock<'a>) -> Result<Self, anyhow::Error> {
-        let heap_ptr = cb.get_heapptr(-1)?;
-        // Make object reachable for garbage collection
-        cb.push_heap_stash();
-        cb.push_pointer(heap_ptr);
-        cb.dup(-3)?;
-        cb.put_prop(-3)?;
-        Ok(Self { heap: heap_ptr, context: cb.context })
+impl Object {
+    /// Creates a new DukObject from the object at the top of the value stack. Fails if the object
+    /// can't be stored in the heap stash, e.g. because the heap is out of memory.
+    fn new(cb: &mut CallBlock) -> DukResult<Self> {
+        unsafe extern "C" fn stash(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+            duk_push_heap_stash(ctx);
+            duk_push_pointer(ctx, udata);
+            duk_push_heapptr(ctx, udata);
+            duk_put_prop(ctx, -3);
+            0
+        }
+
+        let heap_ptr = cb.get_heapptr(-1).map_err(|e| DukError::from_str(e.to_string()))?;
+        if cb.context.heap.state.retain_object(heap_ptr.as_ptr()) {
+            // Make object reachable for garbage collection
+            if cb.safe_call(stash, heap_ptr.as_ptr(), 0) != 0 {
+                let err = cb.error();
+                cb.pop();
+                cb.context.heap.state.release_object(heap_ptr.as_ptr());
+                return Err(err);
+            }
+            cb.pop();
+        }
+        Ok(Self { heap: heap_ptr, context: cb.context.clone() })
     }
 
     /// Encode this object to a JSON string.
     pub fn encode(&self) -> Option<String> {
-        let mut cb = CallBlock::from(self.context);
+        let mut cb = CallBlock::from(&self.context);
         cb.push_heapptr(&self.heap);
-        if cb.is_undefined(-1).unwrap() {
-            None
-        } else {
-            cb.dup(-1).unwrap();
-            match cb.json_encode(-1) {
-                Ok(v) => Some(v),
-                Err(_) => {
-                    None
-                }
-            }
+        if cb.is_undefined(-1).unwrap() || cb.json_encode_safe() != 0 {
+            return None;
+        }
+        match cb.get() {
+            Ok(Value::String(json)) => Some(json),
+            _ => None,
         }
     }
 
     /// Get a property on this object as a DukValue.
     pub fn get(&self, name: &str) -> DukResult<Value> {
-        let mut bl = CallBlock::from(self.context);
+        let mut bl = CallBlock::from(&self.context);
         bl.push_heapptr(&self.heap);
-        if bl.get_prop_lstring(-1, name) == 1 {
-            Ok(bl.get().unwrap())
+        bl.push_lstring(name)?;
+        if bl.get_prop_safe()? {
+            bl.get()
         } else {
             Err(DukError::from(
                 DukErrorCode::Error,
@@ -392,9 +1140,9 @@ impl<'a> Object<'a> {
     }
 
     /// Set a property on this object.
-    pub fn set<'z, T>(&self, name: &str, value: T) -> DukResult<()>
+    pub fn set<T>(&self, name: &str, value: T) -> DukResult<()>
     where
-        T: TryInto<Value<'z>>,
+        T: TryInto<Value>,
     {
         let duk_val = match value.try_into() {
             Ok(v) => v,
@@ -404,7 +1152,7 @@ impl<'a> Object<'a> {
             }
         };
 
-        let mut bl = CallBlock::from(self.context);
+        let mut bl = CallBlock::from(&self.context);
 
         bl.push_heapptr(&self.heap);
         if bl.is_undefined(-1).unwrap() {
@@ -413,45 +1161,55 @@ impl<'a> Object<'a> {
                 "Invalid heap pointer, cannot set property on an undefined object.",
             ));
         }
-        match duk_val {
-            Value::Undefined => bl.push_undefined(),
-            Value::Null => bl.push_null(),
-            Value::Number(n) => {
-                if let Number::NaN = n {
-                    bl.push_nan();
-                } else if let Number::Infinity = n {
-                    bl.push_lstring("Infinity")
-                } else {
-                    bl.push_number(f64::from(n));
-                }
-            }
-            Value::Boolean(b) => bl.push_boolean(b),
-            Value::String(s) => bl.push_lstring(s.as_str()),
-            Value::Object(ref o) => {
-                bl.push_heapptr(&o.heap);
-                if bl.is_undefined(-1).unwrap() {
-                    return Err(DukError::from(
-                        DukErrorCode::Error,
-                        "Error setting property to undefined object.",
-                    ));
-                }
-            }
-        };
+        bl.push_lstring(name)?;
+        bl.push_value(&duk_val)?;
+        if bl.put_prop_safe() == 0 {
+            Ok(())
+        } else {
+            Err(bl.error())
+        }
+    }
+}
 
-        bl.put_prop_lstring(-2, name)?;
-        Ok(())
+impl Object {
+    /// The context this object lives in.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Heap pointer of the object, which stays reachable as long as this value is alive.
+    pub(crate) fn heap_ptr(&self) -> *mut c_void {
+        self.heap.as_ptr()
     }
 }
 
-impl<'a> Drop for Object<'a> {
-    /// Deletes the object from the heap stash and nullifies the internal heap pointer value.
-    /// The object value is useless after calling this and should no longer be used.
+impl Clone for Object {
+    fn clone(&self) -> Self {
+        self.context.heap.state.retain_object(self.heap.as_ptr());
+        Self {
+            context: self.context.clone(),
+            heap: self.heap,
+        }
+    }
+}
+
+impl Drop for Object {
+    /// Deletes the object from the heap stash once no other wrapper references it.
     fn drop(&mut self) {
-        let ctx = self.context.ctx.as_ptr();
-        unsafe {
+        if !self.context.heap.state.release_object(self.heap.as_ptr()) {
+            return;
+        }
+        unsafe extern "C" fn unstash(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
             duk_push_heap_stash(ctx);
-            duk_push_pointer(ctx, self.heap.as_ptr());
+            duk_push_pointer(ctx, udata);
             duk_del_prop(ctx, -2);
+            0
+        }
+
+        let ctx = self.context.as_ptr();
+        unsafe {
+            // Deleting the key may allocate. If that fails, the object is kept alive for good.
+            duk_safe_call(ctx, Some(unstash), self.heap.as_ptr(), 0, 1);
             duk_pop(ctx);
         }
     }
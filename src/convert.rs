@@ -0,0 +1,414 @@
+use crate::context::{Context, Object};
+use crate::error::{DukError, DukErrorCode};
+use crate::function::Function;
+use crate::types::{Number, Value};
+use crate::DukResult;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::BuildHasher;
+
+/// Lists the own enumerable keys of an object.
+const KEYS: &str = "(function (o) { return Object.keys(o); })";
+
+/// Conversion of a JavaScript value into a Rust type. Values of the wrong type are rejected with
+/// a `TypeError`, no implicit JavaScript coercion takes place.
+pub trait FromJs: Sized {
+    /// Converts `value`.
+    fn from_js(value: &Value) -> DukResult<Self>;
+}
+
+/// Conversion of a Rust value into a JavaScript value of the context `ctx`.
+pub trait IntoJs {
+    /// Converts `self`.
+    fn into_js(self, ctx: &Context) -> DukResult<Value>;
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Undefined => "undefined",
+        Value::Null => "null",
+        Value::Number(_) => "number",
+        Value::Boolean(_) => "boolean",
+        Value::String(_) => "string",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_error(expected: &str, value: &Value) -> DukError {
+    DukError::from(
+        DukErrorCode::Type,
+        &format!("expected {}, got {}", expected, type_name(value)),
+    )
+}
+
+fn number(value: &Value) -> DukResult<f64> {
+    match value {
+        Value::Number(n) => Ok(f64::from(n.clone())),
+        _ => Err(type_error("a number", value)),
+    }
+}
+
+fn object(value: &Value) -> DukResult<&Object> {
+    match value {
+        Value::Object(o) => Ok(o),
+        _ => Err(type_error("an object", value)),
+    }
+}
+
+/// Longest array like object converted to a Rust collection. Scripts control `length`, reading
+/// up to 2^32 - 1 elements would tie up the host without ever running into the memory limit.
+const MAX_ELEMENTS: usize = 1 << 24;
+
+/// Reads the elements of an array like object. Each read is protected, an error thrown by a
+/// getter is returned.
+fn elements(value: &Value) -> DukResult<Vec<Value>> {
+    let obj = object(value)?;
+    let len = match obj.get("length")? {
+        Value::Number(n) => f64::from(n),
+        _ => return Err(type_error("an array", value)),
+    };
+    if len.fract() != 0.0 || len < 0.0 {
+        return Err(type_error("an array", value));
+    }
+    if len > MAX_ELEMENTS as f64 {
+        return Err(DukError::from(
+            DukErrorCode::Range,
+            &format!("array of length {} exceeds the limit of {}", len, MAX_ELEMENTS),
+        ));
+    }
+    (0..len as usize).map(|i| obj.get(&i.to_string())).collect()
+}
+
+/// Builds an array out of already converted values.
+fn array(ctx: &Context, values: Vec<Value>) -> DukResult<Value> {
+    let arr = ctx.create_array()?;
+    for (i, value) in values.into_iter().enumerate() {
+        arr.set(&i.to_string(), value)?;
+    }
+    Ok(Value::Object(arr))
+}
+
+impl FromJs for Value {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoJs for Value {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(self)
+    }
+}
+
+impl FromJs for bool {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(type_error("a boolean", value)),
+        }
+    }
+}
+
+impl IntoJs for bool {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl FromJs for String {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            _ => Err(type_error("a string", value)),
+        }
+    }
+}
+
+impl IntoJs for String {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::String(self))
+    }
+}
+
+impl IntoJs for &str {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::from(self))
+    }
+}
+
+impl IntoJs for () {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::Undefined)
+    }
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl FromJs for $t {
+                fn from_js(value: &Value) -> DukResult<Self> {
+                    let n = number(value)?;
+                    // `MAX as f64` rounds up to 2^63 or 2^64 for 64 bit types, compare against
+                    // the exact power of two above `MAX` instead
+                    let above_max = (<$t>::MAX / 2 + 1) as f64 * 2.0;
+                    if n.fract() != 0.0 || n < <$t>::MIN as f64 || n >= above_max {
+                        return Err(DukError::from(
+                            DukErrorCode::Type,
+                            &format!("expected {}, got {}", stringify!($t), n),
+                        ));
+                    }
+                    Ok(n as $t)
+                }
+            }
+
+            impl IntoJs for $t {
+                fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+                    // Too large to be an integer for JavaScript anyway
+                    let n = match TryInto::<i64>::try_into(self) {
+                        Ok(n) => Number::Int(n),
+                        Err(_) => Number::Float(self as f64),
+                    };
+                    Ok(Value::Number(n))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_float {
+    ($($t:ty),*) => {
+        $(
+            impl FromJs for $t {
+                fn from_js(value: &Value) -> DukResult<Self> {
+                    Ok(number(value)? as $t)
+                }
+            }
+
+            impl IntoJs for $t {
+                fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+                    Ok(Value::from(f64::from(self)))
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+impl FromJs for Object {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        object(value).cloned()
+    }
+}
+
+impl IntoJs for Object {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::Object(self))
+    }
+}
+
+impl FromJs for Function {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        value.clone().try_into()
+    }
+}
+
+impl IntoJs for Function {
+    fn into_js(self, _ctx: &Context) -> DukResult<Value> {
+        Ok(Value::from(self))
+    }
+}
+
+/// `undefined` and `null` map to `None`.
+impl<T: FromJs> FromJs for Option<T> {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        match value {
+            Value::Undefined | Value::Null => Ok(None),
+            _ => T::from_js(value).map(Some),
+        }
+    }
+}
+
+/// `None` maps to `null`.
+impl<T: IntoJs> IntoJs for Option<T> {
+    fn into_js(self, ctx: &Context) -> DukResult<Value> {
+        match self {
+            Some(value) => value.into_js(ctx),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+/// A failed conversion is handed over as `Err` instead of failing.
+impl<T: FromJs> FromJs for DukResult<T> {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        Ok(T::from_js(value))
+    }
+}
+
+/// An `Err` is thrown as a JavaScript error.
+impl<T: IntoJs, E: Into<DukError>> IntoJs for Result<T, E> {
+    fn into_js(self, ctx: &Context) -> DukResult<Value> {
+        match self {
+            Ok(value) => value.into_js(ctx),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<T: FromJs> FromJs for Vec<T> {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        elements(value)?.iter().map(T::from_js).collect()
+    }
+}
+
+impl<T: IntoJs> IntoJs for Vec<T> {
+    fn into_js(self, ctx: &Context) -> DukResult<Value> {
+        let values = self
+            .into_iter()
+            .map(|value| value.into_js(ctx))
+            .collect::<DukResult<Vec<_>>>()?;
+        array(ctx, values)
+    }
+}
+
+impl<T: FromJs, S: BuildHasher + Default> FromJs for HashMap<String, T, S> {
+    fn from_js(value: &Value) -> DukResult<Self> {
+        let obj = object(value)?;
+        let keys = obj.context().call_helper("duktape-rs:keys", KEYS, &[value])?;
+        let keys: Vec<String> = FromJs::from_js(&keys)?;
+        keys.into_iter()
+            .map(|key| {
+                let value = T::from_js(&obj.get(&key)?)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+impl<T: IntoJs, S: BuildHasher> IntoJs for HashMap<String, T, S> {
+    fn into_js(self, ctx: &Context) -> DukResult<Value> {
+        let obj = ctx.create_object()?;
+        for (key, value) in self {
+            obj.set(&key, value.into_js(ctx)?)?;
+        }
+        Ok(Value::Object(obj))
+    }
+}
+
+/// Tuples map to arrays of the same length.
+macro_rules! impl_tuple {
+    ($len:expr; $($t:ident $idx:tt),*) => {
+        impl<$($t: FromJs),*> FromJs for ($($t,)*) {
+            fn from_js(value: &Value) -> DukResult<Self> {
+                let elements = elements(value)?;
+                if elements.len() != $len {
+                    return Err(type_error(concat!("an array of length ", $len), value));
+                }
+                Ok(($($t::from_js(&elements[$idx])?,)*))
+            }
+        }
+
+        impl<$($t: IntoJs),*> IntoJs for ($($t,)*) {
+            fn into_js(self, ctx: &Context) -> DukResult<Value> {
+                array(ctx, vec![$(self.$idx.into_js(ctx)?),*])
+            }
+        }
+    };
+}
+
+impl_tuple!(1; A 0);
+impl_tuple!(2; A 0, B 1);
+impl_tuple!(3; A 0, B 1, C 2);
+impl_tuple!(4; A 0, B 1, C 2, D 3);
+impl_tuple!(5; A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// A Rust function which can be called from JavaScript with its arguments converted by `FromJs`
+/// and its result by `IntoJs`. Implemented for functions and closures taking up to 8 arguments.
+pub trait NativeFunction<Args>: 'static {
+    /// Converts the arguments, calls the function and converts its result.
+    fn call_native(&self, ctx: &Context, args: &[Value]) -> DukResult<Value>;
+}
+
+/// Converts the argument at `idx`. A missing argument is read as `undefined`, which makes
+/// `Option` parameters optional.
+#[doc(hidden)]
+pub fn argument<T: FromJs>(args: &[Value], idx: usize) -> DukResult<T> {
+    let res = match args.get(idx) {
+        Some(value) => T::from_js(value),
+        None => T::from_js(&Value::Undefined),
+    };
+    res.map_err(|e| {
+        let message = match args.get(idx) {
+            Some(_) => format!("argument {}: {}", idx + 1, e),
+            None => format!("missing argument {}", idx + 1),
+        };
+        DukError::from(DukErrorCode::Type, &message)
+    })
+}
+
+/// Rejects calls passing more than `arity` arguments.
+#[doc(hidden)]
+pub fn check_arity(args: &[Value], arity: usize) -> DukResult<()> {
+    if args.len() > arity {
+        return Err(DukError::from(
+            DukErrorCode::Type,
+            &format!("expected at most {} arguments, got {}", arity, args.len()),
+        ));
+    }
+    Ok(())
+}
+
+macro_rules! impl_native_function {
+    ($arity:expr; $($arg:ident $idx:tt),*) => {
+        impl<Func, Ret, $($arg),*> NativeFunction<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + 'static,
+            Ret: IntoJs,
+            $($arg: FromJs,)*
+        {
+            #[allow(unused_variables)]
+            fn call_native(&self, ctx: &Context, args: &[Value]) -> DukResult<Value> {
+                check_arity(args, $arity)?;
+                (self)($(argument::<$arg>(args, $idx)?),*).into_js(ctx)
+            }
+        }
+    };
+}
+
+impl_native_function!(0;);
+impl_native_function!(1; A 0);
+impl_native_function!(2; A 0, B 1);
+impl_native_function!(3; A 0, B 1, C 2);
+impl_native_function!(4; A 0, B 1, C 2, D 3);
+impl_native_function!(5; A 0, B 1, C 2, D 3, E 4);
+impl_native_function!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+impl_native_function!(7; A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_native_function!(8; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl Context {
+    /// Creates a JavaScript function calling a plain Rust function, converting its arguments and
+    /// result. Calls with too many arguments, or arguments of the wrong type, throw a `TypeError`.
+    ///
+    /// ```ignore
+    /// let repeat = ctx.create_typed_function(|s: String, n: u32| s.repeat(n as usize))?;
+    /// ```
+    pub fn create_typed_function<F, Args>(&self, func: F) -> DukResult<Function>
+    where
+        F: NativeFunction<Args>,
+    {
+        self.create_function(move |ctx, args| func.call_native(ctx, args))
+    }
+
+    /// Creates a function with `create_typed_function` and makes it available as the global `name`.
+    pub fn register_typed_function<F, Args>(&self, name: &str, func: F) -> DukResult<()>
+    where
+        F: NativeFunction<Args>,
+    {
+        let function = self.create_typed_function(func)?;
+        self.global_object()?.set(name, function)
+    }
+}
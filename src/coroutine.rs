@@ -0,0 +1,104 @@
+use crate::context::{Context, Object};
+use crate::error::DukError;
+use crate::types::Value;
+use crate::DukResult;
+use std::cell::Cell;
+use std::convert::TryInto;
+
+/// Wraps a function into a `Duktape.Thread`, remembering when it returns.
+const CREATE: &str = "(function (fn) {
+    var co = { done: false };
+    co.thread = new Duktape.Thread(function (value) {
+        var res = fn(value);
+        co.done = true;
+        return res;
+    });
+    return co;
+})";
+
+/// `Duktape.Thread.resume` has to be called from JavaScript.
+const RESUME: &str = "(function (co, value) {
+    var res = Duktape.Thread.resume(co.thread, value);
+    return [co.done, res];
+})";
+
+/// What a coroutine did when it was resumed.
+#[derive(Debug)]
+pub enum CoroutineState {
+    /// The coroutine paused with `Duktape.Thread.yield(value)`.
+    Yielded(Value),
+    /// The coroutine function returned, it can't be resumed anymore.
+    Returned(Value),
+}
+
+/// A JavaScript function running as a duktape coroutine, driven from Rust.
+///
+/// The function runs on its own duktape thread until it calls `Duktape.Thread.yield(value)`,
+/// handing `value` back to Rust. The next `resume` continues it, with the resume value returned
+/// by the `yield` call.
+///
+/// ```ignore
+/// let func: Object = ctx.eval_string("(function (x) { var y = Duktape.Thread.yield(x + 1); return y * 2; })")?.try_into()?;
+/// let co = Coroutine::new(&ctx, &func)?;
+/// co.resume(1)?; // Yielded(2)
+/// co.resume(5)?; // Returned(10)
+/// ```
+#[derive(Debug)]
+pub struct Coroutine {
+    context: Context,
+    state: Object,
+    finished: Cell<bool>,
+}
+
+impl Coroutine {
+    /// Creates a coroutine running `func` in `context`. It only starts running on the first `resume`,
+    /// which passes the resume value as argument to `func`.
+    pub fn new(context: &Context, func: &Object) -> DukResult<Coroutine> {
+        let func = Value::Object(func.clone());
+        let state = context.call_helper("duktape-rs:coroutine:create", CREATE, &[&func])?;
+        Ok(Coroutine {
+            context: context.clone(),
+            state: state.try_into()?,
+            finished: Cell::new(false),
+        })
+    }
+
+    /// Resumes the coroutine with `value`, running it until it yields or returns. An error thrown
+    /// by the coroutine is returned as a `DukError`, and finishes it.
+    pub fn resume<T>(&self, value: T) -> DukResult<CoroutineState>
+    where
+        T: TryInto<Value>,
+    {
+        if self.finished.get() {
+            return Err(DukError::from_str("Cannot resume a finished coroutine"));
+        }
+        let value = match value.try_into() {
+            Ok(v) => v,
+            Err(_) => return Err(DukError::from_str("Could not convert parameter to DukValue")),
+        };
+        let state = Value::Object(self.state.clone());
+        let res = self
+            .context
+            .call_helper("duktape-rs:coroutine:resume", RESUME, &[&state, &value]);
+        let res: Object = match res.and_then(|r| r.try_into()) {
+            Ok(res) => res,
+            Err(e) => {
+                self.finished.set(true);
+                return Err(e);
+            }
+        };
+        let done: bool = res.get("0")?.try_into()?;
+        let value = res.get("1")?;
+        if done {
+            self.finished.set(true);
+            Ok(CoroutineState::Returned(value))
+        } else {
+            Ok(CoroutineState::Yielded(value))
+        }
+    }
+
+    /// Returns `true` once the coroutine returned or threw an error.
+    pub fn is_finished(&self) -> bool {
+        self.finished.get()
+    }
+}
@@ -1,9 +1,10 @@
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 use dukbind::{
     duk_int_t, DUK_ERR_ERROR, DUK_ERR_EVAL_ERROR, DUK_ERR_NONE, DUK_ERR_RANGE_ERROR,
-    DUK_ERR_SYNTAX_ERROR, DUK_ERR_TYPE_ERROR, DUK_ERR_URI_ERROR,
+    DUK_ERR_REFERENCE_ERROR, DUK_ERR_SYNTAX_ERROR, DUK_ERR_TYPE_ERROR, DUK_ERR_URI_ERROR,
 };
 
 /// An error code representing why an error occurred.
@@ -15,14 +16,57 @@ pub enum DukErrorCode {
     Error = DUK_ERR_ERROR,
     Eval = DUK_ERR_EVAL_ERROR,
     Range = DUK_ERR_RANGE_ERROR,
+    Reference = DUK_ERR_REFERENCE_ERROR,
     Syntax = DUK_ERR_SYNTAX_ERROR,
     Type = DUK_ERR_TYPE_ERROR,
     URI = DUK_ERR_URI_ERROR,
     NullPtr,
+    /// The heap ran over its memory limit.
+    OutOfMemory,
+    /// The script ran over its time limit.
+    Timeout,
+    /// The script was aborted through an `InterruptHandle`.
+    Interrupted,
 }
 
+impl DukErrorCode {
+    /// Maps an error code as returned by duktape, unknown codes map to `DukErrorCode::Error`.
+    pub(crate) fn from_raw(code: u32) -> DukErrorCode {
+        match code {
+            DUK_ERR_NONE => DukErrorCode::None,
+            DUK_ERR_EVAL_ERROR => DukErrorCode::Eval,
+            DUK_ERR_RANGE_ERROR => DukErrorCode::Range,
+            DUK_ERR_REFERENCE_ERROR => DukErrorCode::Reference,
+            DUK_ERR_SYNTAX_ERROR => DukErrorCode::Syntax,
+            DUK_ERR_TYPE_ERROR => DukErrorCode::Type,
+            DUK_ERR_URI_ERROR => DukErrorCode::URI,
+            _ => DukErrorCode::Error,
+        }
+    }
+
+    /// The duktape error code to throw for this code. Host side codes map to `DUK_ERR_ERROR`.
+    pub(crate) fn to_raw(self) -> u32 {
+        match self {
+            DukErrorCode::Eval => DUK_ERR_EVAL_ERROR,
+            DukErrorCode::Range => DUK_ERR_RANGE_ERROR,
+            DukErrorCode::Reference => DUK_ERR_REFERENCE_ERROR,
+            DukErrorCode::Syntax => DUK_ERR_SYNTAX_ERROR,
+            DukErrorCode::Type => DUK_ERR_TYPE_ERROR,
+            DukErrorCode::URI => DUK_ERR_URI_ERROR,
+            _ => DUK_ERR_ERROR,
+        }
+    }
+}
+
+/// The Rust error a `DukError` was created from, see `DukError::from_error`.
+pub(crate) type ErrorCause = Arc<dyn Error + Send + Sync>;
+
+/// Hidden property of JavaScript errors thrown by native functions, pointing to a boxed
+/// `ErrorCause`.
+pub(crate) const ERROR_CAUSE_KEY: &[u8] = b"\xFFduktape-rs:cause";
+
 /// Error object representing a duktape error.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Debug)]
 pub struct DukError {
     /// The error code, if a specific one is available, or
     /// `ErrorCode::Error` if we have nothing better.
@@ -32,6 +76,13 @@ pub struct DukError {
     /// documentation always just converts them to strings.  So that's all
     /// we'll store for now.
     message: Option<String>,
+
+    /// The script file and line the error was thrown from, when known.
+    file_name: Option<String>,
+    line_number: Option<u32>,
+
+    /// The Rust error this error was created from, kept across JavaScript frames.
+    cause: Option<ErrorCause>,
 }
 
 impl DukError {
@@ -40,6 +91,9 @@ impl DukError {
         DukError {
             code,
             message: None,
+            file_name: None,
+            line_number: None,
+            cause: None,
         }
     }
 
@@ -48,6 +102,9 @@ impl DukError {
         DukError {
             code: DukErrorCode::Error,
             message: Some(String::from(message.as_ref())),
+            file_name: None,
+            line_number: None,
+            cause: None,
         }
     }
 
@@ -56,11 +113,98 @@ impl DukError {
         DukError {
             code,
             message: Some(message.to_string()),
+            file_name: None,
+            line_number: None,
+            cause: None,
+        }
+    }
+
+    /// Create a DukError from a Rust error, keeping it as the cause. When returned from a native
+    /// function, it's thrown as a JavaScript error of the type matching `code`, and the original
+    /// error can be recovered with `downcast_ref` if it propagates back out of the context.
+    pub fn from_error<E>(code: DukErrorCode, err: E) -> DukError
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        DukError {
+            code,
+            message: Some(err.to_string()),
+            file_name: None,
+            line_number: None,
+            cause: Some(Arc::new(err)),
         }
     }
+
+    /// Sets the Rust error this error was created from.
+    pub(crate) fn with_cause(mut self, cause: Option<ErrorCause>) -> DukError {
+        self.cause = cause;
+        self
+    }
+
+    /// Sets the script location the error was thrown from.
+    pub(crate) fn with_location(mut self, file_name: Option<String>, line_number: Option<u32>) -> DukError {
+        self.file_name = file_name;
+        self.line_number = line_number;
+        self
+    }
 }
 
-impl Error for DukError {}
+impl DukError {
+    /// The error code of this error.
+    pub fn code(&self) -> DukErrorCode {
+        self.code
+    }
+
+    /// The error message, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Name of the script file the error was thrown from, if known.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// Line of the script the error was thrown from, if known.
+    pub fn line_number(&self) -> Option<u32> {
+        self.line_number
+    }
+
+    /// The Rust error this error was created from, if any.
+    pub fn cause(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        self.cause.as_deref()
+    }
+
+    /// The Rust error this error was created from, if it is an `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.cause()?.downcast_ref::<E>()
+    }
+
+    pub(crate) fn shared_cause(&self) -> Option<&ErrorCause> {
+        self.cause.as_ref()
+    }
+}
+
+/// Errors are equal if they have the same code, message and location.
+impl PartialEq for DukError {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code
+            && self.message == other.message
+            && self.file_name == other.file_name
+            && self.line_number == other.line_number
+    }
+}
+
+impl Eq for DukError {}
+
+impl Error for DukError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.cause {
+            Some(cause) => Some(&**cause),
+            None => None,
+        }
+    }
+}
 
 impl fmt::Display for DukError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -71,3 +215,93 @@ impl fmt::Display for DukError {
         }
     }
 }
+
+/// A syntax error found by `Context::check_syntax`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SyntaxError {
+    message: String,
+    line: Option<u32>,
+    column: Option<u32>,
+}
+
+impl SyntaxError {
+    /// The message reported by the compiler, such as `parse error (line 3)`.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Line of the source the error was found on, starting at 1.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// Column of the source the error was found on, starting at 1. Duktape only tracks lines, so
+    /// this is `None` for errors it reports.
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+}
+
+/// Why `Context::check_syntax` rejected a source.
+#[derive(PartialEq, Eq, Debug)]
+pub enum CheckError {
+    /// The source is not a valid program.
+    Syntax(SyntaxError),
+    /// The source could not be checked, the heap ran out of memory for instance. This says
+    /// nothing about the source itself.
+    Failed(DukError),
+}
+
+impl From<DukError> for CheckError {
+    fn from(err: DukError) -> Self {
+        if err.code() != DukErrorCode::Syntax {
+            return CheckError::Failed(err);
+        }
+        // The message holds the stack trace, only its first line is of interest
+        let message = err.to_string();
+        let message = message.lines().next().unwrap_or_default();
+        let message = message.strip_prefix("SyntaxError: ").unwrap_or(message);
+        CheckError::Syntax(SyntaxError {
+            message: String::from(message),
+            line: compiler_line(message).or(err.line_number),
+            column: None,
+        })
+    }
+}
+
+impl Error for CheckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheckError::Syntax(err) => Some(err),
+            CheckError::Failed(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckError::Syntax(err) => write!(f, "syntax error: {}", err),
+            CheckError::Failed(err) => write!(f, "syntax check failed: {}", err),
+        }
+    }
+}
+
+/// Extracts the source line the compiler appends to its messages, as in `invalid token (line 3)`.
+fn compiler_line(message: &str) -> Option<u32> {
+    let start = message.rfind("(line ")? + "(line ".len();
+    let digits = message[start..].split(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+impl Error for SyntaxError {}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            (Some(line), None) => write!(f, "{}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
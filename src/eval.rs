@@ -0,0 +1,119 @@
+use crate::context::Context;
+use crate::error::{DukError, DukErrorCode};
+use crate::types::Value;
+use crate::DukResult;
+use dukbind::{DUK_COMPILE_EVAL, DUK_COMPILE_FUNCTION, DUK_COMPILE_STRICT};
+
+/// How the source given to `Context::eval_with` is compiled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvalMode {
+    /// Global code, as a script file. The result is the value of the last expression statement.
+    Program,
+    /// Eval code, as passed to `eval()`. Variables it declares can be deleted afterwards.
+    Eval,
+    /// A single function expression, such as `function (a, b) { return a + b; }`. The function
+    /// is returned without being called.
+    Function,
+}
+
+/// Options of `Context::eval_with`.
+///
+/// ```ignore
+/// let opts = EvalOptions {
+///     filename: String::from("plugins/greet.js"),
+///     strict: true,
+///     ..Default::default()
+/// };
+/// ctx.eval_with(PLUGIN_SOURCE, &opts)?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct EvalOptions {
+    /// Name reported in error locations and stack traces. Defaults to `"eval"`.
+    pub filename: String,
+    /// Compiles the code in strict mode, as if it started with `"use strict";`. Defaults to `false`.
+    pub strict: bool,
+    /// Defaults to `EvalMode::Eval`, the mode of `Context::eval_string`.
+    pub mode: EvalMode,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            filename: String::from("eval"),
+            strict: false,
+            mode: EvalMode::Eval,
+        }
+    }
+}
+
+impl EvalOptions {
+    fn flags(&self) -> u32 {
+        let mode = match self.mode {
+            EvalMode::Program => 0,
+            EvalMode::Eval => DUK_COMPILE_EVAL,
+            EvalMode::Function => DUK_COMPILE_FUNCTION,
+        };
+        if self.strict {
+            mode | DUK_COMPILE_STRICT
+        } else {
+            mode
+        }
+    }
+}
+
+impl Context {
+    /// Evaluates `code` with the given options, returning the resulting value. Program and eval
+    /// code run with the global object as `this`.
+    pub fn eval_with<S: AsRef<[u8]>>(&self, code: S, options: &EvalOptions) -> DukResult<Value> {
+        let function = self.compile_function(code.as_ref(), &options.filename, options.flags())?;
+        match options.mode {
+            EvalMode::Function => Ok(Value::Object(function)),
+            EvalMode::Program | EvalMode::Eval => self.call_function(&function, None, &[]),
+        }
+    }
+}
+
+/// Checks that a binding name can be spliced into a parameter list as is.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+impl Context {
+    /// Runs `code` as the body of a function whose parameters are bound to the given values, and
+    /// returns what the function returns. Nothing is added to the global object, and the values
+    /// never go through the source, so there is nothing to escape.
+    ///
+    /// Binding names must be plain ASCII identifiers.
+    ///
+    /// ```ignore
+    /// let res = ctx.eval_with_bindings(
+    ///     "return user.name + ' has ' + limit + ' credits';",
+    ///     &[("user", user), ("limit", 10.into())],
+    /// )?;
+    /// ```
+    pub fn eval_with_bindings<S: AsRef<[u8]>>(&self, code: S, bindings: &[(&str, Value)]) -> DukResult<Value> {
+        let mut params = Vec::with_capacity(bindings.len());
+        for (name, _) in bindings {
+            if !is_identifier(name) {
+                return Err(DukError::from(
+                    DukErrorCode::Syntax,
+                    &format!("Invalid binding name: {:?}", name),
+                ));
+            }
+            params.push(*name);
+        }
+        // The header stays on the first line, so line numbers match the code
+        let mut source = format!("function ({}) {{", params.join(", ")).into_bytes();
+        source.extend_from_slice(code.as_ref());
+        source.extend_from_slice(b"\n}");
+
+        let function = self.compile_function(&source, "eval", DUK_COMPILE_FUNCTION)?;
+        let args: Vec<&Value> = bindings.iter().map(|(_, value)| value).collect();
+        self.call_function(&function, None, &args)
+    }
+}
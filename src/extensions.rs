@@ -0,0 +1,101 @@
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A type map holding host state for a context, at most one value per type.
+///
+/// The values live alongside the heap and are dropped with it. They are reachable from anywhere
+/// with a `Context`, like native functions, without resorting to globals. Each value is borrowed
+/// on its own, so holding one doesn't keep the others out of reach.
+///
+/// ```ignore
+/// struct RequestId(u64);
+///
+/// ctx.extensions().insert(RequestId(42));
+/// let id = ctx.extensions().get::<RequestId>().unwrap().0;
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    /// Values are boxed so their cells stay put while the map grows, and are never dropped while
+    /// borrowed. That keeps the cells handed out by `cell` alive.
+    map: RefCell<HashMap<TypeId, Box<RefCell<dyn Any>>>>,
+}
+
+impl Extensions {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value, returning the previous value of the same type if there was one.
+    ///
+    /// # Panics
+    /// Panics if the previous value is currently borrowed.
+    pub fn insert<T: 'static>(&self, value: T) -> Option<T> {
+        let mut map = self.map.borrow_mut();
+        let id = TypeId::of::<T>();
+        if let Some(prev) = map.get(&id) {
+            assert!(prev.try_borrow_mut().is_ok(), "Extension replaced while borrowed");
+        }
+        map.insert(id, Box::new(RefCell::new(value)))
+            .map(|prev| unsafe { unbox(prev) })
+    }
+
+    /// Borrows the value of type `T`, if there is one.
+    ///
+    /// # Panics
+    /// Panics if the value is currently mutably borrowed.
+    pub fn get<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.cell::<T>()?.borrow(), |v| v.downcast_ref()).ok()
+    }
+
+    /// Mutably borrows the value of type `T`, if there is one.
+    ///
+    /// # Panics
+    /// Panics if the value is currently borrowed.
+    pub fn get_mut<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.cell::<T>()?.borrow_mut(), |v| v.downcast_mut()).ok()
+    }
+
+    /// Returns `true` if there is a value of type `T`.
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.borrow().contains_key(&TypeId::of::<T>())
+    }
+
+    /// Removes and returns the value of type `T`, if there is one.
+    ///
+    /// # Panics
+    /// Panics if the value is currently borrowed.
+    pub fn remove<T: 'static>(&self) -> Option<T> {
+        let mut map = self.map.borrow_mut();
+        let id = TypeId::of::<T>();
+        if let Some(value) = map.get(&id) {
+            assert!(value.try_borrow_mut().is_ok(), "Extension removed while borrowed");
+        }
+        map.remove(&id).map(|value| unsafe { unbox(value) })
+    }
+
+    /// The cell holding the value of type `T`, outliving the borrow of the map.
+    fn cell<T: 'static>(&self) -> Option<&RefCell<dyn Any>> {
+        let map = self.map.borrow();
+        let cell: *const RefCell<dyn Any> = &**map.get(&TypeId::of::<T>())?;
+        // Boxed and only dropped when not borrowed, see `map`
+        Some(unsafe { &*cell })
+    }
+}
+
+/// Takes the value out of a cell stored under the `TypeId` of `T`.
+///
+/// # Safety
+/// The cell must hold a `T`.
+unsafe fn unbox<T: 'static>(cell: Box<RefCell<dyn Any>>) -> T {
+    Box::from_raw(Box::into_raw(cell) as *mut RefCell<T>).into_inner()
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.borrow().len())
+            .finish()
+    }
+}
@@ -0,0 +1,473 @@
+use crate::context::{Context, Object};
+use crate::error::{DukError, DukErrorCode, ErrorCause, ERROR_CAUSE_KEY};
+use crate::types::Value;
+use crate::DukResult;
+use dukbind::{
+    duk_concat, duk_context, duk_del_prop, duk_dup, duk_del_prop_lstring, duk_errcode_t, duk_get_heapptr, duk_get_pointer, duk_get_prop,
+    duk_get_prop_lstring, duk_normalize_index, duk_pop, duk_push_c_function, duk_push_current_function, duk_push_error_object_raw,
+    duk_push_lstring, duk_push_pointer, duk_put_prop, duk_put_prop_lstring, duk_ret_t, duk_safe_call, duk_set_finalizer,
+    duk_size_t, duk_throw_raw,
+};
+use std::any::Any;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+/// A Rust closure callable from JavaScript. Shared, for a running closure to outlive its function
+/// if a script calls the finalizer by hand.
+type NativeFn = Rc<dyn Fn(&CallContext) -> DukResult<Value>>;
+
+/// Hidden property of native functions holding their boxed closure. The leading 0xFF byte makes
+/// it a hidden symbol, out of reach of scripts.
+const CLOSURE_KEY: &[u8] = b"\xFFduktape-rs:closure";
+/// Appended to the key of a hidden pointer for the key of its owner.
+const OWNER_SUFFIX: &[u8] = b":owner";
+
+/// A JavaScript function, either defined by a script or created with `Context::create_function`.
+///
+/// Functions come out of the context as objects, and are converted with `try_into`:
+///
+/// ```ignore
+/// let callback: Function = ctx.eval_string("(function (a, b) { return a + b; })")?.try_into()?;
+/// let sum = callback.call(&Value::Undefined, &[Value::from(1_i64), Value::from(2_i64)])?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Function {
+    object: Object,
+}
+
+impl Function {
+    /// Calls the function with `this` as its `this` binding. An error thrown by the function is
+    /// returned as a `DukError`.
+    pub fn call(&self, this: &Value, args: &[Value]) -> DukResult<Value> {
+        let args: Vec<&Value> = args.iter().collect();
+        self.object.context().call_function(&self.object, Some(this), &args)
+    }
+
+    /// Sets the magic value of a native function, a 16 bit number read back by the function with
+    /// `CallContext::magic`. It lets one closure back several functions. Fails for functions
+    /// defined in JavaScript.
+    pub fn set_magic(&self, magic: i16) -> DukResult<()> {
+        self.object.context().set_magic(&self.object, i32::from(magic))
+    }
+
+    /// The magic value of a native function, 0 for functions defined in JavaScript.
+    pub fn magic(&self) -> DukResult<i32> {
+        self.object.context().magic(&self.object)
+    }
+
+    /// Calls the function as a constructor, as `new func(...args)` would, returning the new object.
+    pub fn construct(&self, args: &[Value]) -> DukResult<Object> {
+        let args: Vec<&Value> = args.iter().collect();
+        let res = self.object.context().construct_function(&self.object, &args)?;
+        match res {
+            Value::Object(o) => Ok(o),
+            _ => Err(DukError::from_str("Constructor did not return an object")),
+        }
+    }
+
+    /// The function as a plain object.
+    pub fn as_object(&self) -> &Object {
+        &self.object
+    }
+
+    /// Turns the function into a plain object.
+    pub fn into_object(self) -> Object {
+        self.object
+    }
+}
+
+/// The call frame of a native function, handed to closures created with
+/// `Context::create_native_function`.
+pub struct CallContext<'a> {
+    context: &'a Context,
+    args: &'a [Value],
+}
+
+impl<'a> CallContext<'a> {
+    /// The context the function is running in.
+    pub fn context(&self) -> &Context {
+        self.context
+    }
+
+    /// The arguments of the call, as many as the caller passed.
+    pub fn args(&self) -> &[Value] {
+        self.args
+    }
+
+    /// The `this` binding of the call. For a constructor call, it's the object being constructed.
+    /// Fails if the heap is out of memory.
+    pub fn this(&self) -> DukResult<Value> {
+        self.context.native_this()
+    }
+
+    /// Returns `true` if the function was called with `new`.
+    pub fn is_constructor_call(&self) -> bool {
+        self.context.native_is_constructor_call()
+    }
+
+    /// The function being called. Fails if the heap is out of memory.
+    pub fn callee(&self) -> DukResult<Function> {
+        Ok(Function {
+            object: self.context.native_callee()?,
+        })
+    }
+
+    /// The magic value of the function being called, see `Function::set_magic`.
+    pub fn magic(&self) -> i32 {
+        self.context.native_magic()
+    }
+}
+
+impl TryInto<Function> for Object {
+    type Error = DukError;
+
+    fn try_into(self) -> Result<Function, Self::Error> {
+        if self.context().is_function(&self) {
+            Ok(Function { object: self })
+        } else {
+            Err(DukError::from(DukErrorCode::Type, "Object is not a function"))
+        }
+    }
+}
+
+impl TryInto<Function> for Value {
+    type Error = DukError;
+
+    fn try_into(self) -> Result<Function, Self::Error> {
+        match self {
+            Value::Object(o) => o.try_into(),
+            _ => Err(DukError::from(DukErrorCode::Type, "Value is not a function")),
+        }
+    }
+}
+
+impl Object {
+    /// Calls the method `name` of this object, with the object as `this` binding. Fails with a
+    /// `TypeError` if the property is not callable.
+    pub fn call_method(&self, name: &str, args: &[Value]) -> DukResult<Value> {
+        let args: Vec<&Value> = args.iter().collect();
+        self.context().call_method(self, name, &args)
+    }
+
+    /// Defines the property `name` as an accessor: reading it returns what `getter` computes, and
+    /// assigning it calls `setter` with the new value. An error returned by either is thrown to
+    /// the script, which is how `setter` rejects invalid values.
+    ///
+    /// The property is enumerable and configurable, like the ones created by `set`.
+    ///
+    /// ```ignore
+    /// let timeout = Rc::new(Cell::new(30_u32));
+    /// let (get, set) = (timeout.clone(), timeout.clone());
+    /// config.define_accessor(
+    ///     "timeout",
+    ///     move |ctx| get.get().into_js(ctx),
+    ///     move |_ctx, value| match u32::from_js(&value) {
+    ///         Ok(secs) if secs > 0 => Ok(set.set(secs)),
+    ///         _ => Err(DukError::from(DukErrorCode::Range, "timeout must be a positive integer")),
+    ///     },
+    /// )?;
+    /// ```
+    pub fn define_accessor<G, S>(&self, name: &str, getter: G, setter: S) -> DukResult<()>
+    where
+        G: Fn(&Context) -> DukResult<Value> + 'static,
+        S: Fn(&Context, Value) -> DukResult<()> + 'static,
+    {
+        let ctx = self.context();
+        let getter = ctx.create_native_function(move |call| getter(call.context()))?;
+        let setter = ctx.create_native_function(move |call| {
+            let value = call.args().first().cloned().unwrap_or(Value::Undefined);
+            setter(call.context(), value)?;
+            Ok(Value::Undefined)
+        })?;
+        ctx.define_accessor(self, name, Some(getter.as_object()), Some(setter.as_object()), true)
+    }
+
+    /// Defines the read only property `name`, whose value is computed by `getter` each time it's
+    /// read. Assigning it is ignored, or throws a `TypeError` in strict mode.
+    pub fn define_getter<G>(&self, name: &str, getter: G) -> DukResult<()>
+    where
+        G: Fn(&Context) -> DukResult<Value> + 'static,
+    {
+        let ctx = self.context();
+        let getter = ctx.create_native_function(move |call| getter(call.context()))?;
+        ctx.define_accessor(self, name, Some(getter.as_object()), None, true)
+    }
+}
+
+impl From<Function> for Value {
+    fn from(value: Function) -> Self {
+        Value::Object(value.object)
+    }
+}
+
+impl Context {
+    /// Creates a JavaScript function calling `func` with the context and the call arguments.
+    ///
+    /// An error returned by `func` is thrown as a JavaScript error, as is a panic. The closure is
+    /// dropped when the function gets garbage collected.
+    ///
+    /// The closure must not capture the `Context`, a clone of it or an `Object` of the context:
+    /// these keep the heap alive, and the heap keeps the closure alive, so neither is ever freed.
+    /// Use the context handed to `func` instead.
+    ///
+    /// ```ignore
+    /// let greet = ctx.create_function(|_ctx, args| match args.first() {
+    ///     Some(Value::String(name)) => Ok(Value::from(format!("Hello {}", name))),
+    ///     _ => Err(DukError::from(DukErrorCode::Type, "expected a name")),
+    /// })?;
+    /// ```
+    pub fn create_function<F>(&self, func: F) -> DukResult<Function>
+    where
+        F: Fn(&Context, &[Value]) -> DukResult<Value> + 'static,
+    {
+        self.create_native_function(move |call| func(call.context(), call.args()))
+    }
+
+    /// Like `create_function`, with access to the whole call frame: the `this` binding, whether
+    /// it's a constructor call and the function itself. The same goes for what the closure
+    /// captures, the context is reached through `CallContext::context`.
+    ///
+    /// ```ignore
+    /// let describe = ctx.create_native_function(|call| {
+    ///     let this: Object = call.this()?.try_into()?;
+    ///     Ok(this.get("name")?)
+    /// })?;
+    /// ```
+    pub fn create_native_function<F>(&self, func: F) -> DukResult<Function>
+    where
+        F: Fn(&CallContext) -> DukResult<Value> + 'static,
+    {
+        // Boxed twice, duktape can only hold a thin pointer
+        let closure: NativeFn = Rc::new(func);
+        let data = Box::into_raw(Box::new(closure));
+        match self.push_native_function(
+            Some(call_trampoline),
+            Some(finalize_trampoline),
+            CLOSURE_KEY,
+            data as *mut c_void,
+        ) {
+            Ok(object) => Ok(Function { object }),
+            Err(e) => {
+                // The function never got hold of the closure
+                drop(unsafe { Box::from_raw(data) });
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a function with `create_function` and makes it available as the global `name`.
+    pub fn register_function<F>(&self, name: &str, func: F) -> DukResult<()>
+    where
+        F: Fn(&Context, &[Value]) -> DukResult<Value> + 'static,
+    {
+        let function = self.create_function(func)?;
+        self.global_object()?.set(name, function)
+    }
+
+    /// Creates a function with `create_native_function` and makes it available as the global `name`.
+    pub fn register_native_function<F>(&self, name: &str, func: F) -> DukResult<()>
+    where
+        F: Fn(&CallContext) -> DukResult<Value> + 'static,
+    {
+        let function = self.create_native_function(func)?;
+        self.global_object()?.set(name, function)
+    }
+}
+
+/// Pushes the key of the hidden property next to `key` holding the heap pointer of the object
+/// owning the pointer. Built by duktape, so that an error thrown meanwhile leaks nothing.
+///
+/// Property reads follow the prototype chain, so an object inheriting from the owner would see
+/// its pointer too, and free it when collected since duktape also inherits finalizers.
+unsafe fn push_owner_key(ctx: *mut duk_context, key: &[u8]) {
+    duk_push_lstring(ctx, key.as_ptr() as *const c_char, key.len() as duk_size_t);
+    duk_push_lstring(ctx, OWNER_SUFFIX.as_ptr() as *const c_char, OWNER_SUFFIX.len() as duk_size_t);
+    duk_concat(ctx, 2);
+}
+
+/// Stores `data` in the hidden property `key` of the object at `idx`.
+///
+/// May throw, e.g. if the object is frozen. Owns nothing, so it can be called in protected mode.
+pub(crate) unsafe fn put_hidden_pointer(ctx: *mut duk_context, idx: i32, key: &[u8], data: *mut c_void) {
+    let idx = duk_normalize_index(ctx, idx);
+    push_owner_key(ctx, key);
+    duk_push_pointer(ctx, duk_get_heapptr(ctx, idx));
+    duk_put_prop(ctx, idx);
+    duk_push_pointer(ctx, data);
+    duk_put_prop_lstring(ctx, idx, key.as_ptr() as *const c_char, key.len() as duk_size_t);
+}
+
+/// Reads the pointer in the hidden property `key` of the object at `idx`. Null if there is none,
+/// if it's inherited, or if the owner key can't be allocated: the read runs in protected mode.
+pub(crate) unsafe fn hidden_pointer(ctx: *mut duk_context, idx: i32, key: &[u8]) -> *mut c_void {
+    let mut args = HiddenArgs {
+        key,
+        ptr: std::ptr::null_mut(),
+    };
+    duk_dup(ctx, idx);
+    duk_safe_call(ctx, Some(read_hidden), &mut args as *mut HiddenArgs as *mut c_void, 1, 1);
+    duk_pop(ctx);
+    args.ptr
+}
+
+/// The arguments of the protected call reading a hidden pointer. Plain data, since an error
+/// unwinds the call with longjmp.
+struct HiddenArgs<'a> {
+    key: &'a [u8],
+    ptr: *mut c_void,
+}
+
+/// Reads the hidden pointer named in `udata` of the object on top of the stack.
+unsafe extern "C" fn read_hidden(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let args = &mut *(udata as *mut HiddenArgs);
+    push_owner_key(ctx, args.key);
+    duk_get_prop(ctx, 0);
+    let owner = duk_get_pointer(ctx, -1);
+    if owner.is_null() || owner != duk_get_heapptr(ctx, 0) {
+        return 0;
+    }
+    duk_get_prop_lstring(ctx, 0, args.key.as_ptr() as *const c_char, args.key.len() as duk_size_t);
+    args.ptr = duk_get_pointer(ctx, -1);
+    0
+}
+
+/// Removes the hidden property `key` of the object at `idx`.
+pub(crate) unsafe fn delete_hidden(ctx: *mut duk_context, idx: i32, key: &[u8]) {
+    let idx = duk_normalize_index(ctx, idx);
+    duk_del_prop_lstring(ctx, idx, key.as_ptr() as *const c_char, key.len() as duk_size_t);
+    push_owner_key(ctx, key);
+    duk_del_prop(ctx, idx);
+}
+
+unsafe extern "C" fn call_trampoline(ctx: *mut duk_context) -> duk_ret_t {
+    // Throwing unwinds with longjmp, so nothing owning memory may still be alive by then. It all
+    // lives in `call_native`, which only makes protected calls.
+    match call_native(ctx) {
+        Some(nret) => nret,
+        None => {
+            duk_throw_raw(ctx);
+            0
+        }
+    }
+}
+
+/// Runs the closure of the current function, leaving its result on the stack. Returns the number
+/// of return values, or `None` if it failed, with the error left on the stack instead.
+unsafe fn call_native(ctx: *mut duk_context) -> Option<duk_ret_t> {
+    duk_push_current_function(ctx);
+    let closure = hidden_pointer(ctx, -1, CLOSURE_KEY) as *mut NativeFn;
+    duk_pop(ctx);
+
+    let context = match Context::from_raw(ctx) {
+        Some(context) if !closure.is_null() => context,
+        Some(_) => {
+            push_error(ctx, &DukError::from_str("Native function has no closure"));
+            return None;
+        }
+        // The heap is being destroyed, there is no context to call the closure with
+        None => return Some(0),
+    };
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let closure = (*closure).clone();
+        let args = context.native_args()?;
+        let call = CallContext {
+            context: &context,
+            args: &args,
+        };
+        closure(&call)
+    }));
+    let res = match res {
+        Ok(res) => res,
+        Err(payload) => Err(DukError::from_str(format!(
+            "Native function panicked: {}",
+            panic_message(&*payload)
+        ))),
+    };
+    match res.and_then(|value| context.push_native_result(&value)) {
+        Ok(()) => Some(1),
+        Err(e) => {
+            push_error(ctx, &e);
+            None
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
+
+unsafe extern "C" fn finalize_trampoline(ctx: *mut duk_context) -> duk_ret_t {
+    // The function being finalized is the only argument
+    let closure = hidden_pointer(ctx, 0, CLOSURE_KEY) as *mut NativeFn;
+    if !closure.is_null() {
+        // Finalizers may run more than once, make sure the closure is only dropped once
+        delete_hidden(ctx, 0, CLOSURE_KEY);
+        drop_boxed(closure);
+    }
+    0
+}
+
+/// Pushes an error object for `err`, leaving it on the stack for a native function to throw. The
+/// Rust error is attached to it, for `DukError::downcast_ref` to find it if the error makes it back
+/// out. Built in protected mode: if that fails, the error raised meanwhile is left instead.
+unsafe fn push_error(ctx: *mut duk_context, err: &DukError) {
+    let message = CString::new(err.to_string().replace('\0', "\\0")).unwrap();
+    let cause = match err.shared_cause() {
+        Some(cause) => Box::into_raw(Box::new(cause.clone())),
+        None => std::ptr::null_mut(),
+    };
+    let mut args = ErrorArgs {
+        code: err.code().to_raw() as duk_errcode_t,
+        message: message.as_ptr(),
+        cause,
+    };
+    let udata = &mut args as *mut ErrorArgs as *mut c_void;
+    if duk_safe_call(ctx, Some(build_error), udata, 0, 1) != 0 && !cause.is_null() {
+        drop_boxed(cause);
+    }
+}
+
+/// The arguments of the protected call building the error of a native function. Plain data, since
+/// an error unwinds the call with longjmp.
+struct ErrorArgs {
+    code: duk_errcode_t,
+    message: *const c_char,
+    cause: *mut ErrorCause,
+}
+
+/// Pushes the error described in `udata`, handing its cause over to the error object.
+unsafe extern "C" fn build_error(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let args = &*(udata as *const ErrorArgs);
+    let fmt = b"%s\0".as_ptr() as *const c_char;
+    duk_push_error_object_raw(ctx, args.code, std::ptr::null(), 0, fmt, args.message);
+    if !args.cause.is_null() {
+        duk_push_c_function(ctx, Some(finalize_cause), 1);
+        duk_set_finalizer(ctx, -2);
+        put_hidden_pointer(ctx, -1, ERROR_CAUSE_KEY, args.cause as *mut c_void);
+    }
+    1
+}
+
+unsafe extern "C" fn finalize_cause(ctx: *mut duk_context) -> duk_ret_t {
+    let cause = hidden_pointer(ctx, 0, ERROR_CAUSE_KEY) as *mut ErrorCause;
+    if !cause.is_null() {
+        delete_hidden(ctx, 0, ERROR_CAUSE_KEY);
+        drop_boxed(cause);
+    }
+    0
+}
+
+/// Drops a value boxed for duktape. Finalizers can't unwind into duktape, a panicking destructor
+/// is ignored.
+pub(crate) unsafe fn drop_boxed<T: ?Sized>(ptr: *mut T) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(ptr))));
+}
@@ -0,0 +1,347 @@
+use crate::error::DukErrorCode;
+use crate::extensions::Extensions;
+use crate::interrupt::ExecState;
+use dukbind::{duk_context, duk_destroy_heap, duk_get_memory_functions, duk_memory_functions, duk_size_t};
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::ptr::{self, NonNull};
+use std::rc::Weak;
+
+/// Custom allocation function, as accepted by `duk_create_heap`.
+pub type AllocFn = unsafe extern "C" fn(udata: *mut c_void, size: duk_size_t) -> *mut c_void;
+
+/// Custom reallocation function, as accepted by `duk_create_heap`.
+pub type ReallocFn =
+    unsafe extern "C" fn(udata: *mut c_void, ptr: *mut c_void, size: duk_size_t) -> *mut c_void;
+
+/// Custom free function, as accepted by `duk_create_heap`.
+pub type FreeFn = unsafe extern "C" fn(udata: *mut c_void, ptr: *mut c_void);
+
+/// Custom fatal error handler, as accepted by `duk_create_heap`.
+pub type FatalFn = unsafe extern "C" fn(udata: *mut c_void, msg: *const c_char);
+
+/// Callback notified with the message of a fatal error.
+pub(crate) type FatalCallback = Box<dyn Fn(&str)>;
+
+/// A set of user supplied memory functions.
+#[derive(Clone, Copy)]
+pub(crate) struct Allocator {
+    pub(crate) alloc: AllocFn,
+    pub(crate) realloc: ReallocFn,
+    pub(crate) free: FreeFn,
+}
+
+/// Host side state of a heap. A pointer to it is handed to duktape as the heap udata, so every
+/// callback duktape makes into Rust can find its way back to it.
+pub(crate) struct HeapState {
+    pub(crate) user_data: *mut c_void,
+    pub(crate) allocator: Option<Allocator>,
+    pub(crate) fatal_handler: Option<FatalFn>,
+    pub(crate) on_fatal: Option<FatalCallback>,
+    pub(crate) memory: MemoryTracker,
+    pub(crate) exec: ExecState,
+    /// Number of `Object` wrappers alive for each heap pointer kept reachable in the heap stash.
+    pub(crate) object_refs: RefCell<HashMap<usize, usize>>,
+    pub(crate) extensions: Extensions,
+    /// The heap owning this state, so native callbacks can get hold of a `Context`.
+    pub(crate) heap: RefCell<Weak<Heap>>,
+}
+
+impl HeapState {
+    /// Recovers the state from a duktape heap udata pointer.
+    ///
+    /// # Safety
+    /// `udata` must be the pointer that was passed to `duk_create_heap` for a live heap.
+    pub(crate) unsafe fn from_udata<'a>(udata: *mut c_void) -> &'a HeapState {
+        &*(udata as *const HeapState)
+    }
+
+    /// Recovers the state of the heap `ctx` belongs to.
+    ///
+    /// # Safety
+    /// `ctx` must be a live context of a heap created by `ContextBuilder`.
+    pub(crate) unsafe fn from_ctx<'a>(ctx: *mut duk_context) -> &'a HeapState {
+        let mut funcs = duk_memory_functions {
+            alloc_func: None,
+            realloc_func: None,
+            free_func: None,
+            udata: ptr::null_mut(),
+        };
+        duk_get_memory_functions(ctx, &mut funcs);
+        Self::from_udata(funcs.udata)
+    }
+
+    /// Registers a new wrapper of the object at `ptr`. Returns `true` for the first one.
+    pub(crate) fn retain_object(&self, ptr: *mut c_void) -> bool {
+        let mut refs = self.object_refs.borrow_mut();
+        let count = refs.entry(ptr as usize).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    /// Unregisters a wrapper of the object at `ptr`. Returns `true` for the last one.
+    pub(crate) fn release_object(&self, ptr: *mut c_void) -> bool {
+        let mut refs = self.object_refs.borrow_mut();
+        match refs.get_mut(&(ptr as usize)) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            _ => {
+                refs.remove(&(ptr as usize));
+                true
+            }
+        }
+    }
+
+    /// Arms the memory and time accounting for a script execution, until the guard is dropped.
+    pub(crate) fn enter(&self) -> ExecGuard<'_> {
+        if self.exec.depth() == 0 {
+            self.memory.refused.set(None);
+        }
+        self.exec.enter();
+        ExecGuard { state: self }
+    }
+
+    /// Maps the error code of a failed call, telling apart errors caused by the host limits. These
+    /// only apply to script executions, other calls (e.g. compiling) get the error duktape raised.
+    ///
+    /// `alloc_failed` tells if the error is one duktape raises for a failed allocation. A refused
+    /// allocation outlives the error for it if a script catches that, later errors are its own.
+    pub(crate) fn error_code(&self, raw: u32, alloc_failed: bool) -> DukErrorCode {
+        if self.exec.depth() == 0 {
+            DukErrorCode::from_raw(raw)
+        } else if let Some(code) = self.exec.tripped() {
+            code
+        } else if alloc_failed && self.memory.refused.get().is_some() {
+            DukErrorCode::OutOfMemory
+        } else {
+            DukErrorCode::from_raw(raw)
+        }
+    }
+}
+
+/// Guard of a running script execution, see `HeapState::enter`.
+pub(crate) struct ExecGuard<'a> {
+    state: &'a HeapState,
+}
+
+impl<'a> Drop for ExecGuard<'a> {
+    fn drop(&mut self) {
+        self.state.exec.leave();
+        if self.state.exec.depth() == 0 {
+            self.state.memory.refused.set(None);
+        }
+    }
+}
+
+impl fmt::Debug for HeapState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HeapState")
+            .field("user_data", &self.user_data)
+            .field("custom_allocator", &self.allocator.is_some())
+            .field("fatal_handler", &self.fatal_handler.is_some())
+            .field("on_fatal", &self.on_fatal.is_some())
+            .field("memory", &self.memory)
+            .field("exec", &self.exec)
+            .field("objects", &self.object_refs.borrow().len())
+            .field("extensions", &self.extensions)
+            .finish()
+    }
+}
+
+/// Bookkeeping of the bytes allocated by a heap, and the optional budget they must fit in.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryTracker {
+    pub(crate) limit: Cell<Option<usize>>,
+    pub(crate) used: Cell<usize>,
+    pub(crate) peak: Cell<usize>,
+    /// Number of live allocations.
+    pub(crate) blocks: Cell<usize>,
+    /// Size of the last allocation refused for going over the limit, until duktape retries it
+    /// successfully after an emergency garbage collection.
+    pub(crate) refused: Cell<Option<usize>>,
+}
+
+impl MemoryTracker {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit: Cell::new(limit),
+            ..Default::default()
+        }
+    }
+
+    /// Checks if growing the live bytes from `old` to `new` bytes for an allocation fits in the budget.
+    fn admit(&self, old: usize, new: usize) -> bool {
+        match self.limit.get() {
+            Some(limit) if new > old && self.used.get() - old + new > limit => {
+                self.refused.set(Some(new));
+                false
+            }
+            _ => {
+                if self.refused.get() == Some(new) {
+                    self.refused.set(None);
+                }
+                true
+            }
+        }
+    }
+
+    fn record(&self, old: usize, new: usize) {
+        let used = self.used.get() - old + new;
+        self.used.set(used);
+        if used > self.peak.get() {
+            self.peak.set(used);
+        }
+    }
+}
+
+// Every allocation is prefixed with a header holding its requested size, so frees and reallocs can
+// be accounted for. The header is large enough to keep the returned pointer suitably aligned.
+const HEADER_SIZE: usize = 16;
+const ALIGN: usize = 16;
+
+unsafe fn header_of(ptr: *mut c_void) -> *mut u8 {
+    (ptr as *mut u8).sub(HEADER_SIZE)
+}
+
+unsafe fn size_of_block(block: *mut u8) -> usize {
+    *(block as *const usize)
+}
+
+unsafe fn finish_block(block: *mut u8, size: usize) -> *mut c_void {
+    *(block as *mut usize) = size;
+    block.add(HEADER_SIZE) as *mut c_void
+}
+
+fn layout_for(size: usize) -> Option<Layout> {
+    let total = size.checked_add(HEADER_SIZE)?;
+    Layout::from_size_align(total, ALIGN).ok()
+}
+
+unsafe fn raw_alloc(state: &HeapState, size: usize) -> *mut u8 {
+    match (state.allocator, layout_for(size)) {
+        (_, None) => ptr::null_mut(),
+        (Some(a), Some(layout)) => (a.alloc)(state.user_data, layout.size()) as *mut u8,
+        (None, Some(layout)) => alloc::alloc(layout),
+    }
+}
+
+unsafe fn raw_realloc(state: &HeapState, block: *mut u8, old: usize, size: usize) -> *mut u8 {
+    match (state.allocator, layout_for(size)) {
+        (_, None) => ptr::null_mut(),
+        (Some(a), Some(layout)) => {
+            (a.realloc)(state.user_data, block as *mut c_void, layout.size()) as *mut u8
+        }
+        (None, Some(layout)) => {
+            // The old layout was valid when the block was allocated.
+            let old_layout = Layout::from_size_align_unchecked(old + HEADER_SIZE, ALIGN);
+            alloc::realloc(block, old_layout, layout.size())
+        }
+    }
+}
+
+unsafe fn raw_free(state: &HeapState, block: *mut u8, size: usize) {
+    match state.allocator {
+        Some(a) => (a.free)(state.user_data, block as *mut c_void),
+        None => alloc::dealloc(block, Layout::from_size_align_unchecked(size + HEADER_SIZE, ALIGN)),
+    }
+}
+
+pub(crate) unsafe extern "C" fn alloc_trampoline(udata: *mut c_void, size: duk_size_t) -> *mut c_void {
+    let state = HeapState::from_udata(udata);
+    if !state.memory.admit(0, size) {
+        return ptr::null_mut();
+    }
+    let block = raw_alloc(state, size);
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+    state.memory.record(0, size);
+    state.memory.blocks.set(state.memory.blocks.get() + 1);
+    finish_block(block, size)
+}
+
+pub(crate) unsafe extern "C" fn realloc_trampoline(
+    udata: *mut c_void,
+    ptr: *mut c_void,
+    size: duk_size_t,
+) -> *mut c_void {
+    if ptr.is_null() {
+        return alloc_trampoline(udata, size);
+    }
+    if size == 0 {
+        free_trampoline(udata, ptr);
+        return ptr::null_mut();
+    }
+    let state = HeapState::from_udata(udata);
+    let block = header_of(ptr);
+    let old = size_of_block(block);
+    if !state.memory.admit(old, size) {
+        return ptr::null_mut();
+    }
+    let block = raw_realloc(state, block, old, size);
+    if block.is_null() {
+        // The original allocation is left untouched, as realloc semantics require.
+        return ptr::null_mut();
+    }
+    state.memory.record(old, size);
+    finish_block(block, size)
+}
+
+pub(crate) unsafe extern "C" fn free_trampoline(udata: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let state = HeapState::from_udata(udata);
+    let block = header_of(ptr);
+    let size = size_of_block(block);
+    raw_free(state, block, size);
+    state.memory.record(size, 0);
+    state.memory.blocks.set(state.memory.blocks.get() - 1);
+}
+
+/// Fatal error handler of every heap.
+///
+/// Duktape can't be resumed after a fatal error, and neither returning nor unwinding through its C
+/// frames is an option, so the callbacks are notified and the process is aborted. There is no way
+/// to recover the heap, which is why the crate runs everything that may throw in protected mode:
+/// fatal errors are left to genuine internal failures of duktape.
+pub(crate) unsafe extern "C" fn fatal_trampoline(udata: *mut c_void, msg: *const c_char) {
+    let state = HeapState::from_udata(udata);
+    let message = if msg.is_null() {
+        String::from("unknown fatal error")
+    } else {
+        CStr::from_ptr(msg).to_string_lossy().into_owned()
+    };
+    if let Some(callback) = &state.on_fatal {
+        // Unwinding out of here is not allowed, a panicking callback doesn't stop the abort
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&message)));
+    }
+    if let Some(handler) = state.fatal_handler {
+        handler(state.user_data, msg)
+    }
+    process::abort();
+}
+
+/// Owner of a duktape heap. The heap is destroyed once the last `Context` and `Object` referencing it are dropped.
+#[derive(Debug)]
+pub(crate) struct Heap {
+    pub(crate) ctx: NonNull<duk_context>,
+    // Must outlive the heap itself, duktape calls back into it until `duk_destroy_heap` returns.
+    pub(crate) state: Box<HeapState>,
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        unsafe {
+            duk_destroy_heap(self.ctx.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Execution time limits and interruption of running scripts.
+//!
+//! Available with the `exec-timeout` feature. Duktape only checks for these if it was built with
+//! the interrupt counter and the exec timeout check hook pointed at the function exported by this
+//! crate:
+//!
+//! ```c
+//! #define DUK_USE_INTERRUPT_COUNTER
+//! #define DUK_USE_EXEC_TIMEOUT_CHECK(udata) duk_rs_exec_timeout_check(udata)
+//! duk_bool_t duk_rs_exec_timeout_check(void *udata);
+//! ```
+//!
+//! Without the feature, only the nesting of script executions is tracked.
+
+use crate::error::DukErrorCode;
+#[cfg(feature = "exec-timeout")]
+use crate::heap::HeapState;
+#[cfg(feature = "exec-timeout")]
+use dukbind::duk_bool_t;
+use std::cell::Cell;
+#[cfg(feature = "exec-timeout")]
+use std::os::raw::c_void;
+#[cfg(feature = "exec-timeout")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "exec-timeout")]
+use std::sync::Arc;
+#[cfg(feature = "exec-timeout")]
+use std::time::{Duration, Instant};
+
+/// A thread safe handle to abort the script running in a `Context`.
+///
+/// The aborted eval fails with `DukErrorCode::Interrupted`. Interrupting a context that is not
+/// running anything, or that no longer exists, has no effect.
+#[cfg(feature = "exec-timeout")]
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "exec-timeout")]
+impl InterruptHandle {
+    /// Requests the running script to be aborted.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Limits applied to the script execution of a heap.
+#[derive(Debug, Default)]
+pub(crate) struct ExecState {
+    #[cfg(feature = "exec-timeout")]
+    pub(crate) time_limit: Cell<Option<Duration>>,
+    #[cfg(feature = "exec-timeout")]
+    deadline: Cell<Option<Instant>>,
+    depth: Cell<u32>,
+    #[cfg(feature = "exec-timeout")]
+    interrupt: Arc<AtomicBool>,
+    /// Why the running script was aborted, if it was.
+    tripped: Cell<Option<DukErrorCode>>,
+}
+
+impl ExecState {
+    #[cfg(feature = "exec-timeout")]
+    pub(crate) fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: self.interrupt.clone(),
+        }
+    }
+
+    /// Marks the start of a script execution. Only the outermost one arms the limits, nested
+    /// executions (e.g. an eval from a native function) share its deadline.
+    pub(crate) fn enter(&self) {
+        if self.depth.get() == 0 {
+            self.tripped.set(None);
+            #[cfg(feature = "exec-timeout")]
+            {
+                self.interrupt.store(false, Ordering::SeqCst);
+                self.deadline
+                    .set(self.time_limit.get().map(|limit| Instant::now() + limit));
+            }
+        }
+        self.depth.set(self.depth.get() + 1);
+    }
+
+    /// Marks the end of a script execution. The outermost one disarms the limits, so that failures
+    /// outside of an execution aren't blamed on them.
+    pub(crate) fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+        if self.depth.get() == 0 {
+            #[cfg(feature = "exec-timeout")]
+            self.deadline.set(None);
+            self.tripped.set(None);
+        }
+    }
+
+    /// Number of nested executions currently running.
+    pub(crate) fn depth(&self) -> u32 {
+        self.depth.get()
+    }
+
+    /// Why the current execution was aborted, if it was.
+    pub(crate) fn tripped(&self) -> Option<DukErrorCode> {
+        self.tripped.get()
+    }
+
+    /// Returns `true` if the running script must be aborted. Keeps returning `true` once tripped so
+    /// scripts can't swallow the error and carry on.
+    #[cfg(feature = "exec-timeout")]
+    fn check(&self) -> bool {
+        if self.tripped.get().is_some() {
+            return true;
+        }
+        if self.interrupt.load(Ordering::SeqCst) {
+            self.tripped.set(Some(DukErrorCode::Interrupted));
+            return true;
+        }
+        match self.deadline.get() {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.tripped.set(Some(DukErrorCode::Timeout));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Exec timeout hook called periodically by duktape while running scripts.
+///
+/// # Safety
+/// Only meant to be called by duktape, with the udata of a heap created by this crate.
+#[cfg(feature = "exec-timeout")]
+#[no_mangle]
+pub unsafe extern "C" fn duk_rs_exec_timeout_check(udata: *mut c_void) -> duk_bool_t {
+    if udata.is_null() {
+        return 0;
+    }
+    let state = HeapState::from_udata(udata);
+    state.exec.check() as duk_bool_t
+}
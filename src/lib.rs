@@ -1,11 +1,51 @@
+mod actor;
+mod builder;
+mod cache;
+mod class;
 mod context;
+mod convert;
+mod coroutine;
 mod error;
+mod eval;
+mod extensions;
+mod function;
+mod heap;
+mod interrupt;
+mod pool;
+mod script;
+mod snapshot;
+mod stats;
 mod types;
 
+pub use actor::{ContextHandle, ContextThread};
+pub use builder::ContextBuilder;
+pub use cache::ScriptCache;
+pub use class::{Class, ClassBuilder, JsClass, JsMethods};
 pub use context::Context;
 pub use context::Object;
-pub use error::DukError;
-pub use types::{Number, Value};
+pub use convert::{FromJs, IntoJs, NativeFunction};
+pub use coroutine::{Coroutine, CoroutineState};
+pub use error::{CheckError, DukError, DukErrorCode, SyntaxError};
+pub use eval::{EvalMode, EvalOptions};
+pub use extensions::Extensions;
+pub use function::{CallContext, Function};
+pub use heap::{AllocFn, FatalFn, FreeFn, ReallocFn};
+#[cfg(feature = "exec-timeout")]
+pub use interrupt::{duk_rs_exec_timeout_check, InterruptHandle};
+pub use pool::{ContextPool, ContextPoolBuilder, PooledContext};
+pub use script::Script;
+pub use stats::{HeapStats, ObjectInfo};
+pub use types::{Number, OwnedValue, Value};
+
+#[cfg(feature = "derive")]
+pub use duktape_derive::{js_methods, JsClass};
+
+/// Used by the code `duktape-derive` generates, not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::class::IntoInstance;
+    pub use crate::convert::{argument, check_arity};
+}
 
 pub type DukResult<T> = std::result::Result<T, DukError>;
 
@@ -0,0 +1,263 @@
+use crate::builder::ContextBuilder;
+use crate::context::Context;
+use crate::snapshot::Snapshot;
+use crate::DukResult;
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+use std::rc::Rc;
+#[cfg(feature = "exec-timeout")]
+use std::time::Duration;
+
+type BuilderFactory = Box<dyn Fn() -> ContextBuilder>;
+type InitFn = Box<dyn Fn(&Context) -> DukResult<()>>;
+type ReturnCheck = Box<dyn Fn(&Context) -> bool>;
+
+/// Builder for a `ContextPool`.
+pub struct ContextPoolBuilder {
+    context_builder: BuilderFactory,
+    init: Option<InitFn>,
+    on_return: Option<ReturnCheck>,
+    max_size: usize,
+    max_idle: usize,
+    min_idle: usize,
+}
+
+impl ContextPoolBuilder {
+    fn new() -> Self {
+        Self {
+            context_builder: Box::new(ContextBuilder::new),
+            init: None,
+            on_return: None,
+            max_size: 16,
+            max_idle: 16,
+            min_idle: 0,
+        }
+    }
+
+    /// Sets how the heaps of the pool are configured. Defaults to `ContextBuilder::new`.
+    pub fn context_builder<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> ContextBuilder + 'static,
+    {
+        self.context_builder = Box::new(factory);
+        self
+    }
+
+    /// Sets the function run once on every new context, before it's handed out for the first time.
+    pub fn init<F>(mut self, init: F) -> Self
+    where
+        F: Fn(&Context) -> DukResult<()> + 'static,
+    {
+        self.init = Some(Box::new(init));
+        self
+    }
+
+    /// Sets a check run on contexts given back to the pool, after they are reset and found
+    /// unchanged. Returning `false` marks the context as dirty and it's discarded instead of being
+    /// reused.
+    pub fn on_return<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&Context) -> bool + 'static,
+    {
+        self.on_return = Some(Box::new(check));
+        self
+    }
+
+    /// Maximum number of contexts alive at once, handed out or idle. Defaults to 16.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Maximum number of idle contexts kept around for reuse. Defaults to 16.
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Number of contexts created and initialized upfront. Defaults to 0.
+    pub fn min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Creates the pool, warming up `min_idle` contexts.
+    pub fn build(self) -> anyhow::Result<ContextPool> {
+        if self.max_size == 0 {
+            return Err(anyhow::anyhow!("Context pool size must be at least 1"));
+        }
+        let warm = self.min_idle.min(self.max_idle).min(self.max_size);
+        let pool = ContextPool {
+            inner: Rc::new(PoolInner {
+                context_builder: self.context_builder,
+                init: self.init,
+                on_return: self.on_return,
+                max_size: self.max_size,
+                max_idle: self.max_idle,
+                idle: RefCell::new(Vec::with_capacity(warm)),
+                size: Cell::new(0),
+            }),
+        };
+        for _ in 0..warm {
+            let entry = pool.inner.create()?;
+            pool.inner.idle.borrow_mut().push(entry);
+        }
+        Ok(pool)
+    }
+}
+
+/// A context managed by the pool, along with what it takes to reset it.
+struct Entry {
+    context: Context,
+    /// Everything reachable from the global object right after init.
+    snapshot: Snapshot,
+    memory_limit: Option<usize>,
+    #[cfg(feature = "exec-timeout")]
+    time_limit: Option<Duration>,
+}
+
+struct PoolInner {
+    context_builder: BuilderFactory,
+    init: Option<InitFn>,
+    on_return: Option<ReturnCheck>,
+    max_size: usize,
+    max_idle: usize,
+    idle: RefCell<Vec<Entry>>,
+    /// Contexts alive, idle or handed out.
+    size: Cell<usize>,
+}
+
+impl PoolInner {
+    fn create(&self) -> anyhow::Result<Entry> {
+        let context = (self.context_builder)().build()?;
+        if let Some(init) = &self.init {
+            init(&context)?;
+        }
+        let snapshot = match Snapshot::take(&context) {
+            Some(snapshot) => snapshot,
+            None => return Err(anyhow::anyhow!("Could not snapshot the initialized context")),
+        };
+        self.size.set(self.size.get() + 1);
+        Ok(Entry {
+            memory_limit: context.memory_limit(),
+            #[cfg(feature = "exec-timeout")]
+            time_limit: context.time_limit(),
+            context,
+            snapshot,
+        })
+    }
+
+    /// Brings a context back to the state it had after init. Returns `false` if that's not possible.
+    fn reset(&self, entry: &Entry) -> bool {
+        let ctx = &entry.context;
+        // Handles or objects still held elsewhere would see the context being reused
+        if ctx.handle_count() > 1 {
+            return false;
+        }
+        ctx.set_memory_limit(entry.memory_limit);
+        #[cfg(feature = "exec-timeout")]
+        ctx.set_time_limit(entry.time_limit);
+
+        let clean = entry.snapshot.remove_new_globals(ctx) && entry.snapshot.matches(ctx);
+        match &self.on_return {
+            Some(check) if clean => check(ctx),
+            _ => clean,
+        }
+    }
+
+    fn release(&self, entry: Entry, discard: bool) {
+        if !discard && self.idle.borrow().len() < self.max_idle && self.reset(&entry) {
+            self.idle.borrow_mut().push(entry);
+        } else {
+            self.size.set(self.size.get() - 1);
+        }
+    }
+}
+
+/// A pool of initialized contexts, ready to be handed out.
+///
+/// Contexts are given back to the pool when the `PooledContext` guard is dropped. They are reset,
+/// meaning the globals added since init are removed and the limits restored, or discarded if
+/// they can't be reset or are still referenced by clones or objects.
+///
+/// A reset context is also discarded if anything else reachable from the global object changed
+/// since init: a builtin or helper overwritten, a prototype or an object created by init mutated.
+/// This is checked from Rust, on every return. State that isn't held in properties, like variables
+/// captured by closures, is not tracked: init shouldn't leave any that scripts can change.
+///
+/// ```ignore
+/// let pool = ContextPool::builder()
+///     .init(|ctx| ctx.eval_string(HELPERS).map(|_| ()))
+///     .min_idle(4)
+///     .build()?;
+///
+/// let ctx = pool.get()?;
+/// ctx.eval_string("helper(1)")?;
+/// ```
+#[derive(Clone)]
+pub struct ContextPool {
+    inner: Rc<PoolInner>,
+}
+
+impl ContextPool {
+    /// Creates a builder to configure a pool.
+    pub fn builder() -> ContextPoolBuilder {
+        ContextPoolBuilder::new()
+    }
+
+    /// Takes an idle context from the pool, or creates a new one if there is none. Fails if the
+    /// pool is at its maximum size.
+    pub fn get(&self) -> anyhow::Result<PooledContext> {
+        let entry = self.inner.idle.borrow_mut().pop();
+        let entry = match entry {
+            Some(entry) => entry,
+            None if self.inner.size.get() < self.inner.max_size => self.inner.create()?,
+            None => return Err(anyhow::anyhow!("Context pool exhausted")),
+        };
+        Ok(PooledContext {
+            entry: Some(entry),
+            pool: self.inner.clone(),
+            discard: false,
+        })
+    }
+
+    /// Number of idle contexts.
+    pub fn idle(&self) -> usize {
+        self.inner.idle.borrow().len()
+    }
+
+    /// Number of contexts alive, idle or handed out.
+    pub fn size(&self) -> usize {
+        self.inner.size.get()
+    }
+}
+
+/// A context taken from a `ContextPool`, given back to it when dropped.
+pub struct PooledContext {
+    entry: Option<Entry>,
+    pool: Rc<PoolInner>,
+    discard: bool,
+}
+
+impl PooledContext {
+    /// Drops the context instead of giving it back to the pool.
+    pub fn discard(mut self) {
+        self.discard = true;
+    }
+}
+
+impl Deref for PooledContext {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        &self.entry.as_ref().unwrap().context
+    }
+}
+
+impl Drop for PooledContext {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.pool.release(entry, self.discard);
+        }
+    }
+}
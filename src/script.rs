@@ -0,0 +1,175 @@
+use crate::context::{Context, Object};
+use crate::error::{CheckError, DukError};
+use crate::convert::FromJs;
+use crate::types::Value;
+use crate::DukResult;
+use dukbind::DUK_VERSION;
+use std::convert::TryInto;
+
+/// Bytecode header: magic, format version, duktape version, build config, checksum and filename
+/// length.
+const MAGIC: &[u8; 4] = b"DKRS";
+const FORMAT_VERSION: u8 = 2;
+const HEADER_SIZE: usize = 4 + 1 + 4 + 4 + 8 + 4;
+
+/// Build config flags, see `build_config`.
+const CONFIG_FASTINT: u8 = 0x01;
+const CONFIG_PACKED_TVAL: u8 = 0x02;
+
+// The integer type bindgen picks for the constant varies between versions
+#[allow(clippy::unnecessary_cast)]
+pub(crate) const DUKTAPE_VERSION: u32 = DUK_VERSION as u32;
+
+/// A compiled program, ready to be run any number of times without parsing it again.
+///
+/// Errors thrown while compiling or running it point to its filename.
+///
+/// ```ignore
+/// let script = ctx.compile("input.total * 2", "rules/double.js")?;
+/// for _ in 0..1000 {
+///     script.run()?;
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Script {
+    function: Object,
+    filename: String,
+}
+
+impl Script {
+    /// Runs the program with the global object as `this`, returning its completion value.
+    pub fn run(&self) -> DukResult<Value> {
+        self.context().call_function(&self.function, None, &[])
+    }
+
+    /// The filename the script was compiled with.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// The context the script was compiled in.
+    pub fn context(&self) -> &Context {
+        self.function.context()
+    }
+
+    /// Serializes the compiled script, to be loaded later with `Context::load_bytecode`.
+    ///
+    /// The bytecode is tied to the duktape version and build config that produced it, and to this
+    /// crate's format.
+    pub fn to_bytecode(&self) -> DukResult<Vec<u8>> {
+        let dump = self.context().dump_function(&self.function)?;
+        let mut payload = Vec::with_capacity(self.filename.len() + dump.len());
+        payload.extend_from_slice(self.filename.as_bytes());
+        payload.extend_from_slice(&dump);
+
+        let mut bytecode = Vec::with_capacity(HEADER_SIZE + payload.len());
+        bytecode.extend_from_slice(MAGIC);
+        bytecode.push(FORMAT_VERSION);
+        bytecode.extend_from_slice(&DUKTAPE_VERSION.to_le_bytes());
+        bytecode.extend_from_slice(&build_config(self.context())?);
+        bytecode.extend_from_slice(&checksum(&payload).to_le_bytes());
+        bytecode.extend_from_slice(&(self.filename.len() as u32).to_le_bytes());
+        bytecode.extend_from_slice(&payload);
+        Ok(bytecode)
+    }
+}
+
+/// FNV-1a hash, to catch corrupted bytecode before duktape gets to see it.
+fn checksum(data: &[u8]) -> u64 {
+    fnv1a(0xcbf2_9ce4_8422_2325, data)
+}
+
+/// Continues an FNV-1a hash with `data`.
+pub(crate) fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The parts of the duktape build the bytecode layout depends on: fastint support and packed
+/// values as flags, the pointer width in bytes, then the integer and double endianness as listed
+/// in `Duktape.env`.
+fn build_config(ctx: &Context) -> DukResult<[u8; 4]> {
+    let duktape = Object::from_js(&ctx.global_object()?.get("Duktape")?)?;
+    let env = duktape.get("env")?.to_string();
+    let mut parts = env.split(' ');
+    let endianness = parts.next().unwrap_or("").as_bytes();
+    let values = parts.next().unwrap_or("");
+
+    let mut flags = 0;
+    if values.contains('f') {
+        flags |= CONFIG_FASTINT;
+    }
+    if values.starts_with('p') {
+        flags |= CONFIG_PACKED_TVAL;
+    }
+    Ok([
+        flags,
+        std::mem::size_of::<usize>() as u8,
+        endianness.first().copied().unwrap_or(0),
+        endianness.get(1).copied().unwrap_or(0),
+    ])
+}
+
+fn invalid(reason: &str) -> DukError {
+    DukError::from_str(format!("Invalid bytecode: {}", reason))
+}
+
+impl Context {
+    /// Compiles `source` as a program, without running it.
+    pub fn compile(&self, source: &str, filename: &str) -> DukResult<Script> {
+        let function = self.compile_function(source.as_bytes(), filename, 0)?;
+        Ok(Script {
+            function,
+            filename: String::from(filename),
+        })
+    }
+
+    /// Checks that `source` is a valid program by compiling it, without ever running it.
+    /// Failures unrelated to the source, such as running out of memory, are reported as
+    /// `CheckError::Failed`.
+    pub fn check_syntax<S: AsRef<[u8]>>(&self, source: S, filename: &str) -> Result<(), CheckError> {
+        self.compile_function(source.as_ref(), filename, 0)?;
+        Ok(())
+    }
+
+    /// Loads a script from bytecode produced by `Script::to_bytecode`.
+    ///
+    /// Bytecode from another duktape version or build config, or corrupted along the way, is
+    /// rejected.
+    ///
+    /// # Safety
+    /// Duktape doesn't validate the bytecode itself: crafted bytecode passing the header checks
+    /// can read and write memory out of bounds. It must come from a trusted source.
+    pub unsafe fn load_bytecode(&self, bytecode: &[u8]) -> DukResult<Script> {
+        if bytecode.len() < HEADER_SIZE || &bytecode[0..4] != MAGIC {
+            return Err(invalid("not produced by Script::to_bytecode"));
+        }
+        if bytecode[4] != FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+        let version = u32::from_le_bytes(bytecode[5..9].try_into().unwrap());
+        if version != DUKTAPE_VERSION {
+            return Err(invalid(&format!(
+                "compiled by duktape {}, running {}",
+                version, DUKTAPE_VERSION
+            )));
+        }
+        if bytecode[9..13] != build_config(self)? {
+            return Err(invalid("compiled by a duktape with another build config"));
+        }
+        let sum = u64::from_le_bytes(bytecode[13..21].try_into().unwrap());
+        let filename_len = u32::from_le_bytes(bytecode[21..25].try_into().unwrap()) as usize;
+        let payload = &bytecode[HEADER_SIZE..];
+        if checksum(payload) != sum || filename_len > payload.len() {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let filename = match std::str::from_utf8(&payload[..filename_len]) {
+            Ok(filename) => String::from(filename),
+            Err(_) => return Err(invalid("filename is not valid UTF-8")),
+        };
+        let function = self.load_function(&payload[filename_len..])?;
+        Ok(Script { function, filename })
+    }
+}
@@ -0,0 +1,322 @@
+use crate::context::Context;
+use dukbind::{
+    duk_call, duk_context, duk_del_prop, duk_dup, duk_enum, duk_get_boolean, duk_get_heapptr,
+    duk_get_length, duk_get_lstring, duk_get_number, duk_get_prop_desc, duk_get_prop_index, duk_get_prop_lstring,
+    duk_get_prototype, duk_get_type, duk_next, duk_pop, duk_pop_2, duk_push_array, duk_push_global_object,
+    duk_push_heap_stash, duk_push_heapptr, duk_push_lstring, duk_push_null, duk_put_prop_index,
+    duk_put_prop_lstring, duk_ret_t, duk_safe_call, duk_set_prototype, duk_size_t, DUK_ENUM_INCLUDE_NONENUMERABLE,
+    DUK_ENUM_INCLUDE_SYMBOLS, DUK_ENUM_NO_PROXY_BEHAVIOR, DUK_ENUM_OWN_PROPERTIES_ONLY, DUK_TYPE_BOOLEAN,
+    DUK_TYPE_NULL, DUK_TYPE_NUMBER, DUK_TYPE_OBJECT, DUK_TYPE_STRING, DUK_TYPE_UNDEFINED,
+};
+use std::collections::{HashMap, HashSet};
+use std::os::raw::{c_char, c_void};
+use std::slice;
+
+/// Heap stash property keeping the objects of the snapshot alive, so that their addresses can't
+/// be taken over by new objects.
+const PINNED_KEY: &[u8] = b"duktape-rs:snapshot";
+
+/// The fields of a property descriptor, in the order they are kept.
+const DESCRIPTOR_FIELDS: [&[u8]; 6] = [b"value", b"get", b"set", b"writable", b"enumerable", b"configurable"];
+
+/// A value as seen by a snapshot: primitives by value, anything allocated on the heap by identity.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ValueShape {
+    Undefined,
+    Null,
+    Boolean(bool),
+    /// The bits of the number, for NaN to equal itself.
+    Number(u64),
+    String(Vec<u8>),
+    Object(usize),
+    /// Buffers, pointers and lightfuncs, along with their heap pointer if they have one.
+    Other(i32, usize),
+}
+
+/// The state of an object as scripts can observe it.
+#[derive(Debug, PartialEq)]
+struct ObjectShape {
+    prototype: ValueShape,
+    extensible: bool,
+    /// The own properties, including non enumerable and symbol ones, with their descriptor.
+    properties: Vec<(ValueShape, [ValueShape; 6])>,
+}
+
+impl ObjectShape {
+    /// The objects this one refers to through its prototype and properties.
+    fn references(&self) -> impl Iterator<Item = usize> + '_ {
+        let descriptors = self.properties.iter().flat_map(|(_, descriptor)| descriptor.iter());
+        std::iter::once(&self.prototype).chain(descriptors).filter_map(|value| match value {
+            ValueShape::Object(ptr) => Some(*ptr),
+            _ => None,
+        })
+    }
+}
+
+/// The state of every object reachable from the global object of a context, to tell if scripts
+/// changed any of them.
+///
+/// Everything is read through the C API, nothing a script could replace takes part in it. State
+/// kept out of properties, e.g. variables captured by closures or the time of a `Date`, isn't
+/// part of the snapshot.
+pub(crate) struct Snapshot {
+    global: usize,
+    /// `Object.isExtensible` as it was when the snapshot was taken.
+    is_extensible: usize,
+    objects: HashMap<usize, ObjectShape>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of `ctx`. Fails if an object can't be read, e.g. because the heap is out
+    /// of memory.
+    pub(crate) fn take(ctx: &Context) -> Option<Snapshot> {
+        let raw = ctx.as_ptr();
+        unsafe {
+            duk_push_global_object(raw);
+            let global = duk_get_heapptr(raw, -1) as usize;
+            let res = duk_safe_call(raw, Some(find_is_extensible), std::ptr::null_mut(), 1, 1);
+            let is_extensible = duk_get_heapptr(raw, -1) as usize;
+            duk_pop(raw);
+            if res != 0 || is_extensible == 0 {
+                return None;
+            }
+
+            let objects = walk(ctx, vec![global], is_extensible)?;
+            let snapshot = Snapshot {
+                global,
+                is_extensible,
+                objects,
+            };
+            if !snapshot.pin(ctx) {
+                return None;
+            }
+            Some(snapshot)
+        }
+    }
+
+    /// Deletes the global properties added since the snapshot was taken. Returns `false` if one of
+    /// them can't be deleted.
+    pub(crate) fn remove_new_globals(&self, ctx: &Context) -> bool {
+        let known: HashSet<&ValueShape> = self.objects[&self.global]
+            .properties
+            .iter()
+            .map(|(key, _)| key)
+            .collect();
+        let current = match describe(ctx, self.global, self.is_extensible) {
+            Some(shape) => shape,
+            None => return false,
+        };
+        let raw = ctx.as_ptr();
+        for (key, _) in current.properties.iter().filter(|(key, _)| !known.contains(key)) {
+            let key = match key {
+                ValueShape::String(key) => key,
+                _ => return false,
+            };
+            unsafe {
+                duk_push_heapptr(raw, self.global as *mut c_void);
+                let res = duk_safe_call(raw, Some(delete), key as *const Vec<u8> as *mut c_void, 1, 1);
+                duk_pop(raw);
+                if res != 0 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if no object of the snapshot changed since it was taken.
+    pub(crate) fn matches(&self, ctx: &Context) -> bool {
+        self.objects
+            .iter()
+            .all(|(ptr, shape)| describe(ctx, *ptr, self.is_extensible).as_ref() == Some(shape))
+    }
+
+    /// Keeps the objects of the snapshot alive in the heap stash, replacing an earlier snapshot.
+    /// Fails if the heap is out of memory.
+    fn pin(&self, ctx: &Context) -> bool {
+        let pinned: Vec<usize> = self.objects.keys().chain(std::iter::once(&self.is_extensible)).copied().collect();
+        let raw = ctx.as_ptr();
+        unsafe {
+            let res = duk_safe_call(raw, Some(pin_objects), &pinned as *const Vec<usize> as *mut c_void, 0, 1);
+            duk_pop(raw);
+            res == 0
+        }
+    }
+}
+
+/// Reads the current shape of the object at `ptr`, which must be kept alive. Extensibility is
+/// checked with `is_extensible`, or left out if it's null.
+fn describe(ctx: &Context, ptr: usize, is_extensible: usize) -> Option<ObjectShape> {
+    let raw = ctx.as_ptr();
+    unsafe {
+        duk_push_heapptr(raw, ptr as *mut c_void);
+        if duk_safe_call(raw, Some(describe_object), is_extensible as *mut c_void, 1, 1) != 0 {
+            duk_pop(raw);
+            return None;
+        }
+        // [ prototype, extensible, key, descriptor, key, descriptor... ], with no prototype
+        let field = |idx: u32| {
+            duk_get_prop_index(raw, -1, idx);
+            let value = value_shape(raw);
+            duk_pop(raw);
+            value
+        };
+        let len = duk_get_length(raw, -1) as u32;
+        let mut properties = Vec::with_capacity((len as usize).saturating_sub(2) / 2);
+        for idx in (2..len).step_by(2) {
+            duk_get_prop_index(raw, -1, idx + 1);
+            let descriptor = DESCRIPTOR_FIELDS.map(|name| {
+                duk_get_prop_lstring(raw, -1, name.as_ptr() as *const c_char, name.len() as duk_size_t);
+                let value = value_shape(raw);
+                duk_pop(raw);
+                value
+            });
+            duk_pop(raw);
+            properties.push((field(idx), descriptor));
+        }
+        let shape = ObjectShape {
+            prototype: field(0),
+            extensible: field(1) == ValueShape::Boolean(true),
+            properties,
+        };
+        duk_pop(raw);
+        Some(shape)
+    }
+}
+
+/// Walks the objects reachable from `roots` through prototypes and properties, getters and
+/// setters included.
+fn walk(ctx: &Context, roots: Vec<usize>, is_extensible: usize) -> Option<HashMap<usize, ObjectShape>> {
+    let mut objects = HashMap::new();
+    let mut pending = roots;
+    while let Some(ptr) = pending.pop() {
+        if objects.contains_key(&ptr) {
+            continue;
+        }
+        let shape = describe(ctx, ptr, is_extensible)?;
+        pending.extend(shape.references());
+        objects.insert(ptr, shape);
+    }
+    Some(objects)
+}
+
+/// What is reachable from the global object and the heap stash, which keeps alive the values
+/// held by Rust.
+pub(crate) struct Census {
+    pub(crate) objects: Vec<usize>,
+    /// The strings used as property keys or values, duktape interns them all.
+    pub(crate) strings: HashSet<Vec<u8>>,
+}
+
+/// Takes the census of `ctx`. Fails if an object can't be read, e.g. because the heap is out of
+/// memory.
+pub(crate) fn census(ctx: &Context) -> Option<Census> {
+    let raw = ctx.as_ptr();
+    let roots = unsafe {
+        duk_push_global_object(raw);
+        duk_push_heap_stash(raw);
+        let roots = vec![duk_get_heapptr(raw, -2) as usize, duk_get_heapptr(raw, -1) as usize];
+        duk_pop_2(raw);
+        roots
+    };
+    let objects = walk(ctx, roots, 0)?;
+    let mut strings = HashSet::new();
+    for shape in objects.values() {
+        for (key, descriptor) in &shape.properties {
+            for value in std::iter::once(key).chain(descriptor.iter()) {
+                if let ValueShape::String(s) = value {
+                    strings.insert(s.clone());
+                }
+            }
+        }
+    }
+    Some(Census {
+        objects: objects.into_keys().collect(),
+        strings,
+    })
+}
+
+/// Reads the value on top of the stack.
+unsafe fn value_shape(ctx: *mut duk_context) -> ValueShape {
+    match duk_get_type(ctx, -1) as u32 {
+        DUK_TYPE_UNDEFINED => ValueShape::Undefined,
+        DUK_TYPE_NULL => ValueShape::Null,
+        DUK_TYPE_BOOLEAN => ValueShape::Boolean(duk_get_boolean(ctx, -1) == 1),
+        DUK_TYPE_NUMBER => ValueShape::Number(duk_get_number(ctx, -1).to_bits()),
+        DUK_TYPE_STRING => {
+            let mut len: duk_size_t = 0;
+            let ptr = duk_get_lstring(ctx, -1, &mut len);
+            ValueShape::String(slice::from_raw_parts(ptr as *const u8, len).to_vec())
+        }
+        DUK_TYPE_OBJECT => ValueShape::Object(duk_get_heapptr(ctx, -1) as usize),
+        other => ValueShape::Other(other as i32, duk_get_heapptr(ctx, -1) as usize),
+    }
+}
+
+// The protected calls below unwind with longjmp on errors, so they own nothing needing a drop.
+
+/// Replaces the global object with its `Object.isExtensible`.
+unsafe extern "C" fn find_is_extensible(ctx: *mut duk_context, _udata: *mut c_void) -> duk_ret_t {
+    duk_get_prop_lstring(ctx, 0, b"Object".as_ptr() as *const c_char, 6);
+    duk_get_prop_lstring(ctx, -1, b"isExtensible".as_ptr() as *const c_char, 12);
+    1
+}
+
+/// Deletes the property named by the key `udata` points to of the object on top of the stack.
+/// Throws if the property is not configurable.
+unsafe extern "C" fn delete(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let key = &*(udata as *const Vec<u8>);
+    duk_push_lstring(ctx, key.as_ptr() as *const c_char, key.len() as duk_size_t);
+    duk_del_prop(ctx, 0);
+    0
+}
+
+/// Stores the objects whose heap pointers `udata` points to in the heap stash, keeping them alive.
+unsafe extern "C" fn pin_objects(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let pinned = &*(udata as *const Vec<usize>);
+    duk_push_heap_stash(ctx);
+    duk_push_array(ctx);
+    for (idx, ptr) in pinned.iter().enumerate() {
+        duk_push_heapptr(ctx, *ptr as *mut c_void);
+        duk_put_prop_index(ctx, -2, idx as u32);
+    }
+    duk_put_prop_lstring(ctx, -2, PINNED_KEY.as_ptr() as *const c_char, PINNED_KEY.len() as duk_size_t);
+    0
+}
+
+/// Replaces the object on top of the stack with an array holding its prototype, whether it's
+/// extensible according to the function `udata` points to, then each own property key followed by
+/// its descriptor. Neither the array nor the
+/// descriptors have a prototype, reading them can't run a script.
+unsafe extern "C" fn describe_object(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    duk_push_array(ctx);
+    duk_push_null(ctx);
+    duk_set_prototype(ctx, 1);
+
+    duk_get_prototype(ctx, 0);
+    duk_put_prop_index(ctx, 1, 0);
+    if !udata.is_null() {
+        duk_push_heapptr(ctx, udata);
+        duk_dup(ctx, 0);
+        duk_call(ctx, 1);
+        duk_put_prop_index(ctx, 1, 1);
+    }
+
+    let flags = DUK_ENUM_OWN_PROPERTIES_ONLY
+        | DUK_ENUM_INCLUDE_NONENUMERABLE
+        | DUK_ENUM_INCLUDE_SYMBOLS
+        | DUK_ENUM_NO_PROXY_BEHAVIOR;
+    duk_enum(ctx, 0, flags);
+    let mut idx = 2;
+    while duk_next(ctx, 2, 0) == 1 {
+        duk_dup(ctx, -1);
+        duk_get_prop_desc(ctx, 0, 0);
+        duk_push_null(ctx);
+        duk_set_prototype(ctx, -2);
+        duk_put_prop_index(ctx, 1, idx + 1);
+        duk_put_prop_index(ctx, 1, idx);
+        idx += 2;
+    }
+    duk_pop(ctx);
+    1
+}
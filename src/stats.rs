@@ -0,0 +1,191 @@
+use crate::context::{Context, Object};
+use crate::error::{DukError, DukErrorCode};
+use crate::snapshot::census;
+use crate::DukResult;
+use dukbind::{
+    duk_context, duk_gc, duk_get_number, duk_get_prop_lstring, duk_get_type, duk_inspect_value, duk_pop,
+    duk_push_heapptr, duk_push_lstring, duk_push_null, duk_ret_t, duk_safe_call, duk_set_prototype, duk_size_t,
+    DUK_GC_COMPACT, DUK_TYPE_NUMBER,
+};
+use std::os::raw::{c_char, c_void};
+
+/// Statistics of a context heap.
+///
+/// The byte and allocation figures come from the allocator of the heap. Duktape doesn't keep
+/// object or string counts, those are taken by walking what is reachable from the global object
+/// and from the values held by Rust, and sized with `duk_inspect_value`. Garbage waiting for
+/// collection is not counted, run `Context::gc` first to leave none.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapStats {
+    /// Bytes currently allocated by the heap.
+    pub allocated_bytes: usize,
+    /// Highest number of bytes allocated by the heap at once.
+    pub peak_allocated_bytes: usize,
+    /// Number of live allocations. Every object, string and buffer takes at least one.
+    pub allocations: usize,
+    /// Memory limit of the heap, if any.
+    pub memory_limit: Option<usize>,
+    /// Number of objects kept reachable by `Object` values held in Rust.
+    pub rust_objects: usize,
+    /// Number of reachable objects, functions and arrays included.
+    pub objects: usize,
+    /// Bytes taken by the reachable objects: headers, property tables, bytecode and buffer data.
+    pub object_bytes: usize,
+    /// Number of strings in the string table used by the reachable objects, as keys or values.
+    pub strings: usize,
+    /// Bytes taken by these strings.
+    pub string_bytes: usize,
+}
+
+/// Internal details of an object, as reported by `duk_inspect_value`. Fields that don't apply to
+/// the kind of object are `None`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    /// Internal class number.
+    pub class: Option<i64>,
+    /// Reference count.
+    pub refcount: Option<i64>,
+    /// Bytes taken by the object header.
+    pub heap_bytes: Option<i64>,
+    /// Bytes taken by the property table.
+    pub property_bytes: Option<i64>,
+    /// Bytes taken by the bytecode, for compiled functions.
+    pub bytecode_bytes: Option<i64>,
+    /// Bytes taken by the data, for buffers.
+    pub data_bytes: Option<i64>,
+}
+
+impl ObjectInfo {
+    /// Bytes taken by the object, all parts together.
+    fn total_bytes(&self) -> usize {
+        let parts = [self.heap_bytes, self.property_bytes, self.bytecode_bytes, self.data_bytes];
+        parts.iter().flatten().sum::<i64>() as usize
+    }
+}
+
+impl Context {
+    /// Runs a garbage collection. Objects with finalizers may need a second run to be freed.
+    pub fn gc(&self) -> DukResult<()> {
+        unsafe { duk_gc(self.as_ptr(), 0) };
+        Ok(())
+    }
+
+    /// Runs a garbage collection, compacting the heap objects afterwards to release unused memory.
+    pub fn gc_compact(&self) -> DukResult<()> {
+        unsafe { duk_gc(self.as_ptr(), DUK_GC_COMPACT) };
+        Ok(())
+    }
+
+    /// Returns the statistics of the heap. Walks every reachable object, it's meant for monitoring
+    /// rather than for hot paths.
+    pub fn stats(&self) -> DukResult<HeapStats> {
+        let census = census(self).ok_or_else(not_inspectable)?;
+        let raw = self.as_ptr();
+        let mut object_bytes = 0;
+        for ptr in &census.objects {
+            let info = unsafe {
+                duk_push_heapptr(raw, *ptr as *mut c_void);
+                inspect_top(raw)
+            };
+            object_bytes += info.ok_or_else(not_inspectable)?.total_bytes();
+        }
+        let mut string_bytes = 0;
+        for string in &census.strings {
+            let info = unsafe { inspect_string(raw, string) };
+            string_bytes += info.ok_or_else(not_inspectable)?.total_bytes();
+        }
+
+        let state = self.state();
+        Ok(HeapStats {
+            allocated_bytes: state.memory.used.get(),
+            peak_allocated_bytes: state.memory.peak.get(),
+            allocations: state.memory.blocks.get(),
+            memory_limit: state.memory.limit.get(),
+            rust_objects: state.object_refs.borrow().len(),
+            objects: census.objects.len(),
+            object_bytes,
+            strings: census.strings.len(),
+            string_bytes,
+        })
+    }
+}
+
+impl Object {
+    /// Returns the internal details of this object.
+    pub fn inspect(&self) -> DukResult<ObjectInfo> {
+        let ctx = self.context();
+        let info = unsafe {
+            duk_push_heapptr(ctx.as_ptr(), self.heap_ptr());
+            inspect_top(ctx.as_ptr())
+        };
+        info.ok_or_else(not_inspectable)
+    }
+}
+
+fn not_inspectable() -> DukError {
+    DukError::from(DukErrorCode::Error, "Could not inspect the heap")
+}
+
+/// Pops the value on top of the stack, returning its details read with `duk_inspect_value`. Fails
+/// if the heap is out of memory.
+unsafe fn inspect_top(ctx: *mut duk_context) -> Option<ObjectInfo> {
+    let mut info = ObjectInfo {
+        class: None,
+        refcount: None,
+        heap_bytes: None,
+        property_bytes: None,
+        bytecode_bytes: None,
+        data_bytes: None,
+    };
+    let res = duk_safe_call(ctx, Some(inspect_value), &mut info as *mut ObjectInfo as *mut c_void, 1, 1);
+    duk_pop(ctx);
+    if res == 0 {
+        Some(info)
+    } else {
+        None
+    }
+}
+
+/// Returns the details of the string `string`. Strings are interned, pushing one again finds the
+/// existing one, unless it was collected meanwhile.
+unsafe fn inspect_string(ctx: *mut duk_context, string: &[u8]) -> Option<ObjectInfo> {
+    if duk_safe_call(ctx, Some(push_string), &string as *const &[u8] as *mut c_void, 0, 1) != 0 {
+        duk_pop(ctx);
+        return None;
+    }
+    inspect_top(ctx)
+}
+
+// The protected calls below unwind with longjmp on errors, so they own nothing needing a drop.
+
+/// Pushes the string `udata` points to.
+unsafe extern "C" fn push_string(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let string = &*(udata as *const &[u8]);
+    duk_push_lstring(ctx, string.as_ptr() as *const c_char, string.len() as duk_size_t);
+    1
+}
+
+/// Fills the `ObjectInfo` `udata` points to with the `duk_inspect_value` details of the value on
+/// top of the stack. The details get no prototype, reading them can't run a script.
+unsafe extern "C" fn inspect_value(ctx: *mut duk_context, udata: *mut c_void) -> duk_ret_t {
+    let info = &mut *(udata as *mut ObjectInfo);
+    duk_inspect_value(ctx, 0);
+    duk_push_null(ctx);
+    duk_set_prototype(ctx, -2);
+    let field = |name: &str| {
+        duk_get_prop_lstring(ctx, -1, name.as_ptr() as *const c_char, name.len() as duk_size_t);
+        let value = match duk_get_type(ctx, -1) as u32 {
+            DUK_TYPE_NUMBER => Some(duk_get_number(ctx, -1) as i64),
+            _ => None,
+        };
+        duk_pop(ctx);
+        value
+    };
+    info.class = field("class");
+    info.refcount = field("refc");
+    info.heap_bytes = field("hbytes");
+    info.property_bytes = field("pbytes");
+    info.bytecode_bytes = field("bcbytes");
+    info.data_bytes = field("dbytes");
+    0
+}
@@ -46,8 +46,8 @@ impl From<Number> for f64 {
     }
 }
 
-impl<'a> From<Value<'a>> for Number {
-    fn from(value: Value<'a>) -> Self {
+impl From<Value> for Number {
+    fn from(value: Value) -> Self {
         match value {
             Value::Number(v) => v,
             _ => Number::NaN,
@@ -56,17 +56,17 @@ impl<'a> From<Value<'a>> for Number {
 }
 
 /// Represents a JavaScript value type.
-#[derive(Debug)]
-pub enum Value<'a> {
+#[derive(Clone, Debug)]
+pub enum Value {
     Undefined,
     Null,
     Number(Number),
     Boolean(bool),
     String(String),
-    Object(Object<'a>),
+    Object(Object),
 }
 
-impl<'a> fmt::Display for Value<'a> {
+impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Undefined => write!(f, "undefined"),
@@ -82,43 +82,43 @@ impl<'a> fmt::Display for Value<'a> {
     }
 }
 
-impl<'a> From<bool> for Value<'a> {
+impl From<bool> for Value {
     fn from(value: bool) -> Self {
         Value::Boolean(value)
     }
 }
 
-impl<'a> From<String> for Value<'a> {
+impl From<String> for Value {
     fn from(value: String) -> Self {
         Value::String(value)
     }
 }
 
-impl<'a> From<&'a str> for Value<'a> {
+impl From<&str> for Value {
     fn from(value: &str) -> Self {
         Value::String(String::from(value))
     }
 }
 
-impl<'a> From<i64> for Value<'a> {
+impl From<i64> for Value {
     fn from(value: i64) -> Self {
         Value::Number(Number::Int(value))
     }
 }
 
-impl<'a> From<f64> for Value<'a> {
+impl From<f64> for Value {
     fn from(value: f64) -> Self {
         Value::Number(Number::Float(value))
     }
 }
 
-impl<'a> From<Object<'a>> for Value<'a> {
-    fn from(value: Object<'a>) -> Self {
+impl From<Object> for Value {
+    fn from(value: Object) -> Self {
         Value::Object(value)
     }
 }
 
-impl<'a> TryInto<bool> for Value<'a> {
+impl TryInto<bool> for Value {
     type Error = DukError;
 
     fn try_into(self) -> Result<bool, Self::Error> {
@@ -130,7 +130,7 @@ impl<'a> TryInto<bool> for Value<'a> {
     }
 }
 
-impl<'a> TryInto<String> for Value<'a> {
+impl TryInto<String> for Value {
     type Error = DukError;
 
     fn try_into(self) -> Result<String, Self::Error> {
@@ -148,10 +148,10 @@ impl<'a> TryInto<String> for Value<'a> {
     }
 }
 
-impl<'a> TryInto<Object<'a>> for Value<'a> {
+impl TryInto<Object> for Value {
     type Error = DukError;
 
-    fn try_into(self) -> Result<Object<'a>, Self::Error> {
+    fn try_into(self) -> Result<Object, Self::Error> {
         if let Value::Object(o) = self {
             Ok(o)
         } else {
@@ -162,8 +162,8 @@ impl<'a> TryInto<Object<'a>> for Value<'a> {
     }
 }
 
-impl<'a> From<Value<'a>> for i64 {
-    fn from(v: Value<'a>) -> Self {
+impl From<Value> for i64 {
+    fn from(v: Value) -> Self {
         match v {
             Value::Number(n) => n.into(),
             _ => f64::NAN as i64,
@@ -171,11 +171,52 @@ impl<'a> From<Value<'a>> for i64 {
     }
 }
 
-impl<'a> From<Value<'a>> for f64 {
-    fn from(v: Value<'a>) -> Self {
+impl From<Value> for f64 {
+    fn from(v: Value) -> Self {
         match v {
             Value::Number(n) => n.into(),
             _ => f64::NAN,
         }
     }
 }
+
+/// A `Value` detached from its context, so it can be sent across threads. Objects are kept as
+/// their JSON encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedValue {
+    Undefined,
+    Null,
+    Number(Number),
+    Boolean(bool),
+    String(String),
+    Json(String),
+}
+
+impl fmt::Display for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OwnedValue::Undefined => write!(f, "undefined"),
+            OwnedValue::Null => write!(f, "null"),
+            OwnedValue::Number(n) => write!(f, "{}", n),
+            OwnedValue::Boolean(b) => write!(f, "{}", b),
+            OwnedValue::String(s) => write!(f, "{}", s),
+            OwnedValue::Json(json) => write!(f, "{}", json),
+        }
+    }
+}
+
+impl From<&Value> for OwnedValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Undefined => OwnedValue::Undefined,
+            Value::Null => OwnedValue::Null,
+            Value::Number(n) => OwnedValue::Number(n.clone()),
+            Value::Boolean(b) => OwnedValue::Boolean(*b),
+            Value::String(s) => OwnedValue::String(s.clone()),
+            Value::Object(o) => match o.encode() {
+                Some(encoded) => OwnedValue::Json(encoded),
+                None => OwnedValue::Undefined,
+            },
+        }
+    }
+}
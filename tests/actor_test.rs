@@ -0,0 +1,76 @@
+use duktape::{Context, ContextThread, DukErrorCode, DukResult, Number, OwnedValue};
+use std::convert::TryInto;
+use std::panic;
+use std::thread;
+
+#[test]
+fn test_call_on_context_thread() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let handle = worker.handle();
+
+    let res = handle
+        .call(|ctx| -> DukResult<String> { ctx.eval_string("'hello'.toUpperCase()")?.try_into() })
+        .unwrap()
+        .unwrap();
+    assert_eq!(res, "HELLO");
+}
+
+#[test]
+fn test_handle_is_shared_across_threads() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let handle = worker.handle();
+    handle.eval_string("var counter = 0").unwrap();
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let handle = handle.clone();
+            thread::spawn(move || handle.eval_string("++counter").unwrap())
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(
+        handle.eval_string("counter").unwrap(),
+        OwnedValue::Number(Number::Int(4))
+    );
+    assert_eq!(
+        handle.eval_string("({a: [1]})").unwrap(),
+        OwnedValue::Json(String::from("{\"a\":[1]}"))
+    );
+}
+
+#[test]
+fn test_script_errors_are_returned() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let err = worker.handle().eval_string("throw new TypeError('nope')").unwrap_err();
+    let err = err.downcast::<duktape::DukError>().unwrap();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_panics_are_propagated() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let handle = worker.handle();
+
+    let res = panic::catch_unwind(|| handle.call(|_| panic!("boom")));
+    assert!(res.is_err());
+
+    // The thread survives the panic
+    assert!(handle.eval_string("1").is_ok());
+}
+
+#[test]
+fn test_shutdown() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let handle = worker.handle();
+    worker.shutdown();
+    assert!(handle.eval_string("1").is_err());
+}
+
+#[test]
+fn test_spawn_failure() {
+    let res = ContextThread::spawn(|| Err(anyhow::anyhow!("no context")));
+    assert!(res.is_err());
+}
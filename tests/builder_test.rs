@@ -0,0 +1,71 @@
+use duktape::ContextBuilder;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+unsafe extern "C" fn counting_alloc(udata: *mut c_void, size: usize) -> *mut c_void {
+    (*(udata as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+    malloc(size)
+}
+
+unsafe extern "C" fn counting_realloc(udata: *mut c_void, ptr: *mut c_void, size: usize) -> *mut c_void {
+    (*(udata as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+    realloc(ptr, size)
+}
+
+unsafe extern "C" fn plain_free(_udata: *mut c_void, ptr: *mut c_void) {
+    free(ptr)
+}
+
+#[test]
+fn test_build_default() {
+    let ctx = ContextBuilder::new().build().unwrap();
+    let val: i64 = ctx.eval_string("1 + 1").unwrap().into();
+    assert_eq!(val, 2);
+    assert!(ctx.user_data().is_null());
+}
+
+#[test]
+fn test_build_with_custom_allocator() {
+    let calls = Box::new(AtomicUsize::new(0));
+    let udata = &*calls as *const AtomicUsize as *mut c_void;
+
+    let ctx = ContextBuilder::new()
+        .allocator(counting_alloc, counting_realloc, plain_free)
+        .user_data(udata)
+        .build()
+        .unwrap();
+
+    assert_eq!(ctx.user_data(), udata);
+    let before = calls.load(Ordering::SeqCst);
+    assert!(before > 0);
+
+    ctx.eval_string("var big = []; for (var i = 0; i < 1000; i++) big.push({i: i});").unwrap();
+    assert!(calls.load(Ordering::SeqCst) > before);
+    drop(ctx);
+}
+
+#[test]
+fn test_init_hooks_run_in_order() {
+    let ctx = ContextBuilder::new()
+        .with_init(|ctx| ctx.eval_string("var steps = ['first']").map(|_| ()))
+        .with_init(|ctx| ctx.eval_string("steps.push('second')").map(|_| ()))
+        .build()
+        .unwrap();
+
+    let steps: String = ctx.eval_string("steps.join(',')").unwrap().to_string();
+    assert_eq!(steps, "first,second");
+}
+
+#[test]
+fn test_init_hook_error_fails_build() {
+    let res = ContextBuilder::new()
+        .with_init(|ctx| ctx.eval_string("throw new Error('boom')").map(|_| ()))
+        .build();
+    assert!(res.is_err());
+}
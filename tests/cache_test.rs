@@ -0,0 +1,129 @@
+use duktape::{Context, ScriptCache};
+use std::fs;
+use std::path::PathBuf;
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("duktape-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn entries(dir: &PathBuf) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("dkbc"))
+        .collect()
+}
+
+#[test]
+fn test_cache_reuses_entries() {
+    let dir = cache_dir("reuse");
+    let cache = unsafe { ScriptCache::new(&dir) }.unwrap();
+
+    let ctx = Context::new().unwrap();
+    let script = cache.compile(&ctx, "6 * 7", "answer.js").unwrap();
+    assert_eq!(script.filename(), "answer.js");
+    assert_eq!(entries(&dir).len(), 1);
+
+    // A fresh context, as after a restart, loads the stored entry
+    let ctx = Context::new().unwrap();
+    let script = cache.compile(&ctx, "6 * 7", "answer.js").unwrap();
+    let val: i64 = script.run().unwrap().into();
+    assert_eq!(val, 42);
+    assert_eq!(entries(&dir).len(), 1);
+
+    // Another filename is another entry
+    cache.compile(&ctx, "6 * 7", "other.js").unwrap();
+    assert_eq!(entries(&dir).len(), 2);
+
+    cache.clear().unwrap();
+    assert!(entries(&dir).is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_recompiles_corrupt_entries() {
+    let dir = cache_dir("corrupt");
+    let cache = unsafe { ScriptCache::new(&dir) }.unwrap();
+    let ctx = Context::new().unwrap();
+    cache.compile(&ctx, "1 + 1", "two.js").unwrap();
+
+    let entry = entries(&dir).pop().unwrap();
+    fs::write(&entry, b"garbage").unwrap();
+
+    let script = cache.compile(&ctx, "1 + 1", "two.js").unwrap();
+    let val: i64 = script.run().unwrap().into();
+    assert_eq!(val, 2);
+    assert!(fs::read(&entry).unwrap().starts_with(b"DKRS"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used() {
+    let dir = cache_dir("evict");
+    let ctx = Context::new().unwrap();
+    let cache = unsafe { ScriptCache::new(&dir) }.unwrap();
+    cache.compile(&ctx, "1", "one.js").unwrap();
+    let size = fs::metadata(entries(&dir).pop().unwrap()).unwrap().len();
+
+    // Room for about two entries
+    let cache = cache.max_size(size * 2 + size / 2);
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    cache.compile(&ctx, "2", "two.js").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    // Using the first entry again makes the second one the oldest
+    cache.compile(&ctx, "1", "one.js").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    cache.compile(&ctx, "3", "three.js").unwrap();
+
+    let names: Vec<String> = entries(&dir)
+        .iter()
+        .map(|p| fs::read(p).unwrap())
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().any(|n| n.contains("one.js")));
+    assert!(names.iter().any(|n| n.contains("three.js")));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_checks_the_source_on_hits() {
+    let dir = cache_dir("collision");
+    let cache = unsafe { ScriptCache::new(&dir) }.unwrap();
+    let ctx = Context::new().unwrap();
+    cache.compile(&ctx, "'first'", "same.js").unwrap();
+    let first = entries(&dir).pop().unwrap();
+    cache.compile(&ctx, "'second'", "same.js").unwrap();
+    let second = entries(&dir).into_iter().find(|p| *p != first).unwrap();
+
+    // As if both sources had the same key
+    fs::copy(&first, &second).unwrap();
+    let script = cache.compile(&ctx, "'second'", "same.js").unwrap();
+    assert_eq!(script.run().unwrap().to_string(), "second");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_concurrent_stores() {
+    let dir = cache_dir("concurrent");
+    let cache = unsafe { ScriptCache::new(&dir) }.unwrap();
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                let ctx = Context::new().unwrap();
+                let script = cache.compile(&ctx, "6 * 7", "answer.js").unwrap();
+                let val: i64 = script.run().unwrap().into();
+                assert_eq!(val, 42);
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    // Every write had its own temporary file, none is left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
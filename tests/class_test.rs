@@ -0,0 +1,148 @@
+use duktape::{CallContext, Class, Context, DukErrorCode, DukResult, FromJs, Function, Object, Value};
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+#[derive(Debug)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+fn arg<T: FromJs>(call: &CallContext, idx: usize) -> DukResult<T> {
+    T::from_js(call.args().get(idx).unwrap_or(&Value::Undefined))
+}
+
+fn register_point(ctx: &Context) -> Class<Point> {
+    ctx.register_class::<Point>("Point")
+        .constructor(|call| Ok(Point { x: arg(call, 0)?, y: arg(call, 1)? }))
+        .method("norm", |p, _call| Ok(Value::from((p.x * p.x + p.y * p.y).sqrt())))
+        .method("scale", |p, call| {
+            let k: f64 = arg(call, 0)?;
+            p.x *= k;
+            p.y *= k;
+            call.this()
+        })
+        .method("toString", |p, _call| Ok(Value::from(format!("({}, {})", p.x, p.y))))
+        .static_method("origin", |call| {
+            let ctor = Function::from_js(&call.this()?)?;
+            Ok(Value::Object(ctor.construct(&[Value::from(0_i64), Value::from(0_i64)])?))
+        })
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_class_from_js() {
+    let ctx = Context::new().unwrap();
+    register_point(&ctx);
+    let norm: f64 = ctx.eval_string("new Point(3, 4).norm()").unwrap().into();
+    assert_eq!(norm, 5.0);
+    let res = ctx.eval_string("String(new Point(1, 2).scale(3))").unwrap().to_string();
+    assert_eq!(res, "(3, 6)");
+    let ok: bool = FromJs::from_js(
+        &ctx.eval_string("var p = new Point(1, 1); p instanceof Point && p.constructor === Point")
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(ok);
+    let res = ctx.eval_string("Point.origin().toString()").unwrap().to_string();
+    assert_eq!(res, "(0, 0)");
+}
+
+#[test]
+fn test_class_errors() {
+    let ctx = Context::new().unwrap();
+    register_point(&ctx);
+
+    let err = ctx.eval_string("Point(1, 2)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("new Point('a', 2)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("Point.prototype.norm.call({ x: 1, y: 1 })").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("Point.prototype.norm()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_class_instance_from_rust() {
+    let ctx = Context::new().unwrap();
+    let class = register_point(&ctx);
+    let p = class.instance(Point { x: 6.0, y: 8.0 }).unwrap();
+    let global: Object = ctx.eval_string("this").unwrap().try_into().unwrap();
+    global.set("p", p.clone()).unwrap();
+    let norm: f64 = ctx.eval_string("p.scale(0.5).norm()").unwrap().into();
+    assert_eq!(norm, 5.0);
+
+    let x = class.with_instance(&p, |p| p.x).unwrap();
+    assert_eq!(x, 3.0);
+    let plain = ctx.create_object().unwrap();
+    assert!(class.with_instance(&plain, |p| p.x).is_err());
+}
+
+struct Resource(Rc<Cell<u32>>);
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn test_class_instances_dropped() {
+    let ctx = Context::new().unwrap();
+    let dropped = Rc::new(Cell::new(0));
+    let counter = dropped.clone();
+    ctx.register_class::<Resource>("Resource")
+        .constructor(move |_call| Ok(Resource(counter.clone())))
+        .method("close", |_res, _call| Ok(Value::Undefined))
+        .build()
+        .unwrap();
+
+    ctx.eval_string("for (var i = 0; i < 3; i++) new Resource().close()").unwrap();
+    ctx.gc().unwrap();
+    assert_eq!(dropped.get(), 3);
+
+    ctx.eval_string("var kept = new Resource()").unwrap();
+    ctx.gc().unwrap();
+    assert_eq!(dropped.get(), 3);
+    drop(ctx);
+    assert_eq!(dropped.get(), 4);
+}
+
+#[test]
+fn test_class_reentrancy() {
+    let ctx = Context::new().unwrap();
+    ctx.register_class::<Point>("Point")
+        .constructor(|_call| Ok(Point { x: 1.0, y: 1.0 }))
+        .method("visit", |_p, call| {
+            let this = Object::from_js(&call.this()?)?;
+            // The value is borrowed by this very call
+            this.call_method("visit2", &[])
+        })
+        .method("visit2", |_p, _call| Ok(Value::Undefined))
+        .build()
+        .unwrap();
+    let err = ctx.eval_string("new Point().visit()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_class_inherited_instance() {
+    let ctx = Context::new().unwrap();
+    let class = register_point(&ctx);
+
+    // Collecting an object inheriting from the instance leaves the instance's value alone
+    ctx.eval_string("var p = new Point(3, 4); Object.create(p); undefined").unwrap();
+    ctx.gc().unwrap();
+    let norm: f64 = ctx.eval_string("p.norm()").unwrap().into();
+    assert_eq!(norm, 5.0);
+
+    // And such objects are not instances
+    let err = ctx.eval_string("Object.create(p).norm()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let child: Object = ctx.eval_string("Object.create(p)").unwrap().try_into().unwrap();
+    let err = class.with_instance(&child, |p| p.x).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
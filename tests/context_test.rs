@@ -41,3 +41,44 @@ fn test_eval_to_object() {
     let val = ctx.eval_string("({\"some\":\"thing\"})").unwrap();
     let _: Object = val.try_into().unwrap();
 }
+
+#[test]
+fn test_clone_context_shares_heap() {
+    let ctx = Context::new().unwrap();
+    let other = ctx.clone();
+    assert!(ctx.ptr_eq(&other));
+
+    ctx.eval_string("var shared = 42").unwrap();
+    drop(ctx);
+
+    let val: i64 = other.eval_string("shared").unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_object_outlives_context() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx.eval_string("({alive: true})").unwrap().try_into().unwrap();
+    drop(ctx);
+
+    let val: bool = obj.get("alive").unwrap().try_into().unwrap();
+    assert!(val);
+}
+
+#[test]
+fn test_eval_source_with_nul() {
+    let ctx = Context::new().unwrap();
+    let val = ctx.eval_string("'a\0b' + '\\u0000c'").unwrap();
+    assert_eq!(val.to_string(), "a\0b\0c");
+
+    let len: i64 = ctx.eval_string(b"'x\0y'.length; // \0 in a comment").unwrap().into();
+    assert_eq!(len, 3);
+}
+
+#[test]
+fn test_eval_bytes_slice() {
+    let ctx = Context::new().unwrap();
+    let source = b"1 + 2; garbage after the end";
+    let val: i64 = ctx.eval_string(&source[..5]).unwrap().into();
+    assert_eq!(val, 3);
+}
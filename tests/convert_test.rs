@@ -0,0 +1,155 @@
+use duktape::{Context, DukError, DukErrorCode, FromJs, IntoJs, Value};
+use std::collections::HashMap;
+
+fn round_trip<T: IntoJs + FromJs>(ctx: &Context, value: T) -> T {
+    let js = value.into_js(ctx).unwrap();
+    T::from_js(&js).unwrap()
+}
+
+#[test]
+fn test_numbers() {
+    let ctx = Context::new().unwrap();
+    assert_eq!(round_trip(&ctx, -5_i8), -5);
+    assert_eq!(round_trip(&ctx, 65535_u16), 65535);
+    assert_eq!(round_trip(&ctx, u32::MAX), u32::MAX);
+    assert_eq!(round_trip(&ctx, 1.5_f32), 1.5);
+    assert_eq!(round_trip(&ctx, 0.25_f64), 0.25);
+
+    let big = ctx.eval_string("300").unwrap();
+    assert_eq!(u8::from_js(&big).unwrap_err().code(), DukErrorCode::Type);
+    let frac = ctx.eval_string("1.5").unwrap();
+    assert!(i32::from_js(&frac).is_err());
+    let neg_frac = ctx.eval_string("-1.5").unwrap();
+    assert_eq!(i32::from_js(&neg_frac).unwrap_err().code(), DukErrorCode::Type);
+    assert_eq!(f64::from_js(&neg_frac).unwrap(), -1.5);
+    assert_eq!(round_trip(&ctx, -1.5_f64), -1.5);
+    let neg = ctx.eval_string("-1").unwrap();
+    assert!(u64::from_js(&neg).is_err());
+    let text = ctx.eval_string("'1'").unwrap();
+    assert!(f64::from_js(&text).is_err());
+
+    // 2^63 and 2^64 are just past the range, even though `MAX as f64` rounds up to them
+    let past_i64 = ctx.eval_string("Math.pow(2, 63)").unwrap();
+    assert!(i64::from_js(&past_i64).is_err());
+    assert_eq!(u64::from_js(&past_i64).unwrap(), 1 << 63);
+    let past_u64 = ctx.eval_string("Math.pow(2, 64)").unwrap();
+    assert!(u64::from_js(&past_u64).is_err());
+    let min = ctx.eval_string("-Math.pow(2, 63)").unwrap();
+    assert_eq!(i64::from_js(&min).unwrap(), i64::MIN);
+}
+
+#[test]
+fn test_containers() {
+    let ctx = Context::new().unwrap();
+    assert_eq!(round_trip(&ctx, vec![1_u8, 2, 3]), vec![1, 2, 3]);
+    assert_eq!(round_trip(&ctx, Some(String::from("a"))), Some(String::from("a")));
+    assert_eq!(round_trip::<Option<i32>>(&ctx, None), None);
+    assert_eq!(round_trip(&ctx, (1_i32, String::from("x"), true)), (1, String::from("x"), true));
+
+    let mut map = HashMap::new();
+    map.insert(String::from("a"), vec![1_i64]);
+    map.insert(String::from("b"), vec![2, 3]);
+    assert_eq!(round_trip(&ctx, map.clone()), map);
+
+    let json = vec![(1_i32, 2_i32)].into_js(&ctx).unwrap().to_string();
+    assert_eq!(json, "[[1,2]]");
+
+    let nested = ctx.eval_string("({ xs: [1, 2], ys: [] })").unwrap();
+    let parsed: HashMap<String, Vec<u32>> = FromJs::from_js(&nested).unwrap();
+    assert_eq!(parsed["xs"], vec![1, 2]);
+    assert!(parsed["ys"].is_empty());
+
+    let pair = ctx.eval_string("[1, 2, 3]").unwrap();
+    assert!(<(i32, i32)>::from_js(&pair).is_err());
+}
+
+#[test]
+fn test_result() {
+    let ctx = Context::new().unwrap();
+    let err: Result<i32, DukError> = Err(DukError::from(DukErrorCode::Range, "nope"));
+    assert_eq!(err.into_js(&ctx).unwrap_err().code(), DukErrorCode::Range);
+
+    let text = ctx.eval_string("'x'").unwrap();
+    let res = <Result<i32, DukError>>::from_js(&text).unwrap();
+    assert!(res.is_err());
+}
+
+fn repeat(s: String, n: u32) -> String {
+    s.repeat(n as usize)
+}
+
+#[test]
+fn test_typed_functions() {
+    let ctx = Context::new().unwrap();
+    ctx.register_typed_function("repeat", repeat).unwrap();
+    ctx.register_typed_function("sum", |xs: Vec<f64>| xs.iter().sum::<f64>()).unwrap();
+    ctx.register_typed_function("greet", |name: Option<String>| {
+        format!("hello {}", name.unwrap_or_else(|| String::from("you")))
+    })
+    .unwrap();
+    ctx.register_typed_function("bytes", |n: u8| -> Vec<u8> { (0..n).collect() }).unwrap();
+    ctx.register_typed_function("nothing", || ()).unwrap();
+
+    assert_eq!(ctx.eval_string("repeat('ab', 3)").unwrap().to_string(), "ababab");
+    let sum: f64 = ctx.eval_string("sum([1, 2, 3.5])").unwrap().into();
+    assert_eq!(sum, 6.5);
+    assert_eq!(ctx.eval_string("greet()").unwrap().to_string(), "hello you");
+    assert_eq!(ctx.eval_string("greet('ann')").unwrap().to_string(), "hello ann");
+    assert_eq!(ctx.eval_string("bytes(3)").unwrap().to_string(), "[0,1,2]");
+    assert!(matches!(ctx.eval_string("nothing()").unwrap(), Value::Undefined));
+}
+
+#[test]
+fn test_typed_function_type_errors() {
+    let ctx = Context::new().unwrap();
+    ctx.register_typed_function("repeat", repeat).unwrap();
+
+    for call in &["repeat(1, 2)", "repeat('a')", "repeat('a', -1)", "repeat('a', 1, 2)"] {
+        let caught = ctx
+            .eval_string(format!("try {{ {}; 'ok' }} catch (e) {{ e.name }}", call))
+            .unwrap()
+            .to_string();
+        assert_eq!(caught, "TypeError", "{}", call);
+    }
+    let err = ctx.eval_string("repeat('a')").unwrap_err();
+    assert!(err.message().unwrap().contains("missing argument 2"));
+}
+
+#[test]
+fn test_typed_function_errors() {
+    let ctx = Context::new().unwrap();
+    ctx.register_typed_function("checked", |n: i32| -> Result<i32, DukError> {
+        if n < 0 {
+            Err(DukError::from(DukErrorCode::Range, "negative"))
+        } else {
+            Ok(n * 2)
+        }
+    })
+    .unwrap();
+    let val: i64 = ctx.eval_string("checked(21)").unwrap().into();
+    assert_eq!(val, 42);
+    let err = ctx.eval_string("checked(-1)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+}
+
+#[test]
+fn test_hostile_arrays() {
+    let ctx = Context::new().unwrap();
+    ctx.register_typed_function("sum", |xs: Vec<f64>| xs.iter().sum::<f64>()).unwrap();
+
+    let caught = ctx
+        .eval_string("try { sum({length: 1, get 0() { throw new Error('boom'); }}) } catch (e) { 'caught' }")
+        .unwrap()
+        .to_string();
+    assert_eq!(caught, "caught");
+
+    let huge = ctx.eval_string("({length: 4294967295})").unwrap();
+    assert!(Vec::<f64>::from_js(&huge).is_err());
+    let err = ctx.eval_string("sum({length: 4294967295})").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let odd = ctx.eval_string("({length: 1.5, 0: 1})").unwrap();
+    assert!(Vec::<f64>::from_js(&odd).is_err());
+
+    let sum: f64 = ctx.eval_string("sum({length: 2, 0: 1, get 1() { return 2; }})").unwrap().into();
+    assert_eq!(sum, 3.0);
+}
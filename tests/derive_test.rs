@@ -0,0 +1,144 @@
+use duktape::{CallContext, Context, DukError, DukErrorCode, DukResult, FromJs};
+use duktape_derive::{js_methods, JsClass};
+
+#[derive(Debug, JsClass)]
+struct Point {
+    #[js(get, set)]
+    x: f64,
+    #[js(get)]
+    y: f64,
+}
+
+#[js_methods]
+impl Point {
+    #[js(constructor)]
+    fn new(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    fn norm(&self) -> f64 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    #[js(name = "scaleBy")]
+    fn scale(&mut self, k: f64, factor: Option<f64>) {
+        let k = k * factor.unwrap_or(1.0);
+        self.x *= k;
+        self.y *= k;
+    }
+
+    fn origin() -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    #[js(get)]
+    fn label(&self, call: &CallContext) -> DukResult<String> {
+        let tag = String::from_js(&call.context().eval_string("typeof tag === 'undefined' ? '' : tag")?)?;
+        Ok(format!("{}({}, {})", tag, self.x, self.y))
+    }
+
+    #[js(set)]
+    fn set_label(&mut self, label: String) -> DukResult<()> {
+        let coords: Vec<f64> = label.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+        match coords[..] {
+            [x, y] => {
+                self.x = x;
+                self.y = y;
+                Ok(())
+            }
+            _ => Err(DukError::from(DukErrorCode::Range, "expected 'x, y'")),
+        }
+    }
+
+    #[js(skip)]
+    #[allow(dead_code)]
+    fn internal(&self) -> Point {
+        Point { x: self.x, y: self.y }
+    }
+}
+
+#[derive(JsClass)]
+#[js(name = "Counter")]
+struct RustCounter(#[js(get, name = "count")] u32);
+
+#[js_methods]
+impl RustCounter {
+    #[js(constructor)]
+    fn new(start: Option<u32>) -> Result<RustCounter, DukError> {
+        match start {
+            Some(start) if start > 100 => Err(DukError::from(DukErrorCode::Range, "start too high")),
+            start => Ok(RustCounter(start.unwrap_or(0))),
+        }
+    }
+
+    fn increment(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+fn eval<T: FromJs>(ctx: &Context, code: &str) -> T {
+    T::from_js(&ctx.eval_string(code).unwrap()).unwrap()
+}
+
+#[test]
+fn test_derive_methods() {
+    let ctx = Context::new().unwrap();
+    ctx.register_js_class::<Point>().unwrap();
+
+    assert_eq!(eval::<f64>(&ctx, "new Point(3, 4).norm()"), 5.0);
+    assert_eq!(eval::<f64>(&ctx, "var p = new Point(1, 2); p.scaleBy(2, 1.5); p.y"), 6.0);
+    assert_eq!(eval::<f64>(&ctx, "p.scaleBy(0.5); p.x"), 1.5);
+    assert_eq!(eval::<Vec<f64>>(&ctx, "Point.origin()"), vec![0.0, 0.0]);
+    assert!(eval::<bool>(&ctx, "p instanceof Point && p.internal === undefined"));
+}
+
+#[test]
+fn test_derive_accessors() {
+    let ctx = Context::new().unwrap();
+    ctx.register_js_class::<Point>().unwrap();
+
+    assert_eq!(eval::<f64>(&ctx, "var p = new Point(1, 2); p.x = 5; p.x + p.y"), 7.0);
+    assert_eq!(eval::<f64>(&ctx, "p.y = 10; p.y"), 2.0);
+    assert_eq!(eval::<String>(&ctx, "var tag = 'P'; p.label"), "P(5, 2)");
+    assert_eq!(eval::<f64>(&ctx, "p.label = '3, 4'; p.norm()"), 5.0);
+    assert!(eval::<bool>(&ctx, "Object.keys(p).length === 0 && 'x' in p"));
+
+    let err = ctx.eval_string("p.x = 'a'").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("p.label = 'nope'").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    let err = ctx.eval_string("'use strict'; p.y = 1").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_derive_arguments() {
+    let ctx = Context::new().unwrap();
+    ctx.register_js_class::<Point>().unwrap();
+
+    let err = ctx.eval_string("new Point(1)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("new Point(1, 'a')").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("new Point(1, 2).norm(3)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx.eval_string("Point.prototype.norm.call({})").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_derive_tuple_struct() {
+    let ctx = Context::new().unwrap();
+    let class = ctx.register_js_class::<RustCounter>().unwrap();
+
+    assert_eq!(eval::<u32>(&ctx, "var c = new Counter(); c.increment(); c.increment()"), 2);
+    assert_eq!(eval::<u32>(&ctx, "c.count"), 2);
+    assert_eq!(eval::<u32>(&ctx, "new Counter(41).increment()"), 42);
+    let err = ctx.eval_string("new Counter(101)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+
+    let c = class.instance(RustCounter(7)).unwrap();
+    c.call_method("increment", &[]).unwrap();
+    assert_eq!(class.with_instance(&c, |c| c.0).unwrap(), 8);
+}
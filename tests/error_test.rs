@@ -0,0 +1,126 @@
+use duktape::{Context, DukError, DukErrorCode, Value};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+struct QuotaExceeded {
+    used: u32,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "quota exceeded: {} used", self.used)
+    }
+}
+
+impl Error for QuotaExceeded {}
+
+fn context_with_quota() -> Context {
+    let ctx = Context::new().unwrap();
+    ctx.register_function("consume", |_ctx, _args| {
+        Err(DukError::from_error(DukErrorCode::Range, QuotaExceeded { used: 11 }))
+    })
+    .unwrap();
+    ctx
+}
+
+#[test]
+fn test_error_subtypes() {
+    let ctx = Context::new().unwrap();
+    ctx.register_typed_function("fail", |code: u32| -> Result<(), DukError> {
+        let code = match code {
+            0 => DukErrorCode::Error,
+            1 => DukErrorCode::Type,
+            2 => DukErrorCode::Range,
+            3 => DukErrorCode::Reference,
+            4 => DukErrorCode::Syntax,
+            5 => DukErrorCode::Eval,
+            6 => DukErrorCode::URI,
+            _ => DukErrorCode::Timeout,
+        };
+        Err(DukError::from(code, "failed"))
+    })
+    .unwrap();
+
+    let names = ctx
+        .eval_string(
+            "var names = [];
+            for (var i = 0; i < 8; i++) {
+                try { fail(i); } catch (e) { names.push(e instanceof Error ? e.name + ':' + e.message : 'not an error'); }
+            }
+            names.join(',')",
+        )
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        names,
+        "Error:failed,TypeError:failed,RangeError:failed,ReferenceError:failed,\
+         SyntaxError:failed,EvalError:failed,URIError:failed,Error:failed"
+    );
+}
+
+#[test]
+fn test_error_caught_in_script() {
+    let ctx = context_with_quota();
+    let res = ctx
+        .eval_string("try { consume(); } catch (e) { e instanceof RangeError && e.message }")
+        .unwrap()
+        .to_string();
+    assert_eq!(res, "quota exceeded: 11 used");
+}
+
+#[test]
+fn test_error_cause_propagates() {
+    let ctx = context_with_quota();
+    let err = ctx.eval_string("consume()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    assert_eq!(err.downcast_ref::<QuotaExceeded>(), Some(&QuotaExceeded { used: 11 }));
+    assert!(err.source().is_some());
+
+    // Rethrown by the script, still the same error
+    let err = ctx
+        .eval_string("try { consume(); } catch (e) { e.extra = 1; throw e; }")
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<QuotaExceeded>().map(|e| e.used), Some(11));
+
+    // Errors raised by scripts have no cause
+    let err = ctx.eval_string("throw new RangeError('plain')").unwrap_err();
+    assert!(err.cause().is_none());
+    assert!(err.downcast_ref::<QuotaExceeded>().is_none());
+}
+
+#[test]
+fn test_error_cause_not_inherited() {
+    let ctx = context_with_quota();
+
+    // Collecting an object inheriting from the error leaves the error's cause alone
+    ctx.eval_string("var err; try { consume(); } catch (e) { err = e; Object.create(e); } undefined")
+        .unwrap();
+    ctx.gc().unwrap();
+    let err = ctx.eval_string("throw err").unwrap_err();
+    assert_eq!(err.downcast_ref::<QuotaExceeded>(), Some(&QuotaExceeded { used: 11 }));
+
+    // Nor does such an object carry the cause when thrown
+    let err = ctx.eval_string("throw Object.create(err)").unwrap_err();
+    assert!(err.cause().is_none());
+}
+
+#[test]
+fn test_error_cause_through_nested_calls() {
+    let ctx = context_with_quota();
+    ctx.eval_string("function inner() { consume(); }").unwrap();
+    ctx.register_function("outer", |ctx, _args| {
+        ctx.eval_string("inner()")?;
+        Ok(Value::Undefined)
+    })
+    .unwrap();
+
+    let err = ctx.eval_string("outer()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    assert!(err.downcast_ref::<QuotaExceeded>().is_some());
+
+    // Still usable with anyhow
+    let err: anyhow::Error = err.into();
+    let err = err.downcast_ref::<DukError>().unwrap();
+    assert!(err.downcast_ref::<QuotaExceeded>().is_some());
+}
@@ -0,0 +1,102 @@
+use duktape::{Context, DukErrorCode, EvalMode, EvalOptions, Object};
+use std::convert::TryInto;
+
+#[test]
+fn test_eval_with_defaults() {
+    let ctx = Context::new().unwrap();
+    let val: i64 = ctx.eval_with("var a = 20; a + 22", &EvalOptions::default()).unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_eval_with_strict() {
+    let ctx = Context::new().unwrap();
+    let sloppy = EvalOptions {
+        mode: EvalMode::Program,
+        ..Default::default()
+    };
+    ctx.eval_with("undeclared = 1", &sloppy).unwrap();
+
+    let strict = EvalOptions {
+        filename: String::from("plugin.js"),
+        strict: true,
+        mode: EvalMode::Program,
+    };
+    let err = ctx.eval_with("\n\nanotherUndeclared = 1", &strict).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Reference);
+    assert_eq!(err.file_name(), Some("plugin.js"));
+    // Nothing was prepended to the source, so line numbers are right
+    assert_eq!(err.line_number(), Some(3));
+
+    let this_kind = ctx
+        .eval_with("(function () { return typeof this; })()", &strict)
+        .unwrap()
+        .to_string();
+    assert_eq!(this_kind, "undefined");
+}
+
+#[test]
+fn test_eval_with_function_mode() {
+    let ctx = Context::new().unwrap();
+    let opts = EvalOptions {
+        mode: EvalMode::Function,
+        ..Default::default()
+    };
+    let func: Object = ctx
+        .eval_with("function (a, b) { return a * b; }", &opts)
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    let global: Object = ctx.eval_string("this").unwrap().try_into().unwrap();
+    global.set("mul", func).unwrap();
+    let val: i64 = ctx.eval_string("mul(6, 7)").unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_eval_with_syntax_error() {
+    let ctx = Context::new().unwrap();
+    let opts = EvalOptions {
+        filename: String::from("broken.js"),
+        ..Default::default()
+    };
+    let err = ctx.eval_with("var = 1", &opts).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+    assert_eq!(err.file_name(), Some("broken.js"));
+}
+
+#[test]
+fn test_eval_with_bindings() {
+    let ctx = Context::new().unwrap();
+    let user = ctx.decode_json(r#"{"name": "ann", "credits": 7}"#).unwrap();
+    let res = ctx
+        .eval_with_bindings(
+            "return user.name + ':' + Math.min(user.credits, limit);",
+            &[("user", user), ("limit", 5_i64.into())],
+        )
+        .unwrap();
+    assert_eq!(res.to_string(), "ann:5");
+
+    // Values are never spliced into the source
+    let tricky = "'); throw new Error('injected'); ('";
+    let res = ctx.eval_with_bindings("return s.length", &[("s", tricky.into())]).unwrap();
+    assert_eq!(i64::from(res), tricky.len() as i64);
+
+    // Nor do the bindings leak into the globals
+    let kind = ctx.eval_string("typeof user").unwrap().to_string();
+    assert_eq!(kind, "undefined");
+}
+
+#[test]
+fn test_eval_with_bindings_errors() {
+    let ctx = Context::new().unwrap();
+    let err = ctx
+        .eval_with_bindings("return 1", &[("a) { evil(); } (function (", 1_i64.into())])
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+
+    let err = ctx.eval_with_bindings("\n\nreturn missing;", &[]).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Reference);
+    assert_eq!(err.line_number(), Some(3));
+}
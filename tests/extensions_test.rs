@@ -0,0 +1,64 @@
+use duktape::{Context, Value};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+struct RequestId(u64);
+
+struct Counter(u32);
+
+#[test]
+fn test_insert_and_get() {
+    let ctx = Context::new().unwrap();
+    assert!(ctx.extensions().get::<RequestId>().is_none());
+
+    assert_eq!(ctx.extensions().insert(RequestId(1)), None);
+    assert_eq!(ctx.extensions().insert(RequestId(2)), Some(RequestId(1)));
+    assert_eq!(*ctx.extensions().get::<RequestId>().unwrap(), RequestId(2));
+    assert!(!ctx.extensions().contains::<Counter>());
+}
+
+#[test]
+fn test_get_mut() {
+    let ctx = Context::new().unwrap();
+    ctx.extensions().insert(Counter(0));
+    for _ in 0..3 {
+        ctx.extensions().get_mut::<Counter>().unwrap().0 += 1;
+    }
+    assert_eq!(ctx.extensions().get::<Counter>().unwrap().0, 3);
+    assert_eq!(ctx.extensions().remove::<Counter>().map(|c| c.0), Some(3));
+    assert!(ctx.extensions().get::<Counter>().is_none());
+}
+
+#[test]
+fn test_shared_between_handles_and_dropped_with_heap() {
+    let marker = Rc::new(());
+    let ctx = Context::new().unwrap();
+    ctx.extensions().insert(marker.clone());
+
+    let other = ctx.clone();
+    assert!(other.extensions().contains::<Rc<()>>());
+    assert_eq!(Rc::strong_count(&marker), 2);
+
+    drop(ctx);
+    assert_eq!(Rc::strong_count(&marker), 2);
+    drop(other);
+    assert_eq!(Rc::strong_count(&marker), 1);
+}
+
+#[test]
+fn test_values_borrowed_independently() {
+    let ctx = Context::new().unwrap();
+    ctx.extensions().insert(Counter(0));
+    ctx.extensions().insert(RequestId(7));
+    ctx.register_function("requestId", |ctx, _args| {
+        let id = ctx.extensions().get::<RequestId>().unwrap().0;
+        Ok(Value::from(id as i64))
+    })
+    .unwrap();
+
+    let mut counter = ctx.extensions().get_mut::<Counter>().unwrap();
+    let id: i64 = ctx.eval_string("requestId()").unwrap().into();
+    counter.0 += id as u32;
+    drop(counter);
+    assert_eq!(ctx.extensions().get::<Counter>().unwrap().0, 7);
+}
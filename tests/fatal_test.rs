@@ -0,0 +1,70 @@
+use duktape::{Context, DukErrorCode, FromJs, Object};
+use std::convert::TryInto;
+
+// A genuine fatal error aborts the process, so it can't be tested here. These are the errors that
+// used to be uncaught and fatal before the operations raising them ran in protected mode.
+
+#[test]
+fn test_decode_error_is_not_fatal() {
+    let ctx = Context::new().unwrap();
+
+    let err = ctx.decode_json("{not json").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+
+    assert_eq!(f64::from_js(&ctx.eval_string("1 + 1").unwrap()).unwrap(), 2.0);
+    assert_eq!(ctx.decode_json("[1, 2]").unwrap().to_string(), "[1,2]");
+}
+
+#[test]
+fn test_throwing_getter() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx
+        .eval_string("({get a() { throw new RangeError('no a'); }, b: 1})")
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    let err = obj.get("a").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    assert_eq!(f64::from_js(&obj.get("b").unwrap()).unwrap(), 1.0);
+}
+
+#[test]
+fn test_throwing_setter() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx
+        .eval_string("({set a(v) { throw new RangeError('no a'); }})")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let err = obj.set("a", 1).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+
+    let frozen: Object = ctx.eval_string("Object.freeze({a: 1})").unwrap().try_into().unwrap();
+    let err = frozen.set("a", 2).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    assert_eq!(f64::from_js(&frozen.get("a").unwrap()).unwrap(), 1.0);
+}
+
+#[test]
+fn test_throwing_to_json() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx
+        .eval_string("({a: {toJSON: function () { throw new Error('no JSON'); }}})")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(obj.encode(), None);
+
+    let cyclic: Object = ctx.eval_string("var o = {}; o.self = o; o").unwrap().try_into().unwrap();
+    assert_eq!(cyclic.encode(), None);
+}
+
+#[test]
+fn test_throwing_error_properties() {
+    let ctx = Context::new().unwrap();
+
+    let res = ctx.eval_string("throw {get fileName() { throw 1; }, get stack() { throw 2; }}");
+    assert!(res.is_err());
+    assert_eq!(f64::from_js(&ctx.eval_string("1 + 1").unwrap()).unwrap(), 2.0);
+}
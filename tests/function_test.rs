@@ -0,0 +1,340 @@
+use duktape::{Context, DukError, DukErrorCode, Function, Object, Value};
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+fn arg_f64(args: &[Value], idx: usize) -> f64 {
+    match args.get(idx) {
+        Some(Value::Number(n)) => f64::from(n.clone()),
+        _ => f64::NAN,
+    }
+}
+
+#[test]
+fn test_register_function() {
+    let ctx = Context::new().unwrap();
+    ctx.register_function("add", |_ctx, args| Ok(Value::from(arg_f64(args, 0) + arg_f64(args, 1))))
+        .unwrap();
+    let val: f64 = ctx.eval_string("add(1.5, 2)").unwrap().into();
+    assert_eq!(val, 3.5);
+
+    let nargs: i64 = {
+        ctx.register_function("count", |_ctx, args| Ok(Value::from(args.len() as i64)))
+            .unwrap();
+        ctx.eval_string("count() + count(1, 'a', {}, [])").unwrap().into()
+    };
+    assert_eq!(nargs, 4);
+}
+
+#[test]
+fn test_function_captures_state() {
+    let ctx = Context::new().unwrap();
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    ctx.register_function("tick", move |_ctx, _args| {
+        counter.set(counter.get() + 1);
+        Ok(Value::Undefined)
+    })
+    .unwrap();
+    ctx.eval_string("for (var i = 0; i < 5; i++) tick()").unwrap();
+    assert_eq!(calls.get(), 5);
+}
+
+#[test]
+fn test_function_reenters_context() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var base = 40").unwrap();
+    ctx.register_function("withBase", |ctx, args| {
+        let base: f64 = ctx.eval_string("base")?.into();
+        Ok(Value::from(base + arg_f64(args, 0)))
+    })
+    .unwrap();
+    let val: i64 = ctx.eval_string("withBase(2)").unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_function_errors_are_thrown() {
+    let ctx = Context::new().unwrap();
+    ctx.register_function("fail", |_ctx, _args| {
+        Err(DukError::from(DukErrorCode::Type, "no good"))
+    })
+    .unwrap();
+
+    let caught = ctx
+        .eval_string("try { fail(); 'not thrown' } catch (e) { e.name + ': ' + e.message }")
+        .unwrap()
+        .to_string();
+    assert_eq!(caught, "TypeError: no good");
+
+    let err = ctx.eval_string("fail()").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_function_panics_are_thrown() {
+    let ctx = Context::new().unwrap();
+    ctx.register_function("boom", |_ctx, _args| panic!("kaboom")).unwrap();
+    let caught = ctx
+        .eval_string("try { boom() } catch (e) { e.message }")
+        .unwrap()
+        .to_string();
+    assert!(caught.contains("kaboom"));
+    // The context is still usable
+    let val: i64 = ctx.eval_string("1 + 1").unwrap().into();
+    assert_eq!(val, 2);
+}
+
+struct DropFlag(Rc<Cell<bool>>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+#[test]
+fn test_closure_freed_with_function() {
+    let ctx = Context::new().unwrap();
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    let func = ctx
+        .create_function(move |_ctx, _args| {
+            let _ = &flag;
+            Ok(Value::Undefined)
+        })
+        .unwrap();
+    assert!(!dropped.get());
+    drop(func);
+    ctx.gc().unwrap();
+    assert!(dropped.get());
+
+    // Closures of functions still alive are freed with the heap
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    ctx.register_function("keep", move |_ctx, _args| {
+        let _ = &flag;
+        Ok(Value::Undefined)
+    })
+    .unwrap();
+    drop(ctx);
+    assert!(dropped.get());
+}
+
+#[test]
+fn test_closure_not_freed_by_inheriting_objects() {
+    let ctx = Context::new().unwrap();
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    ctx.register_function("answer", move |_ctx, _args| {
+        let _ = &flag;
+        Ok(Value::from(42_i64))
+    })
+    .unwrap();
+
+    // The child inherits the finalizer of the function, which must leave the closure alone
+    ctx.eval_string("Object.create(answer); undefined").unwrap();
+    ctx.gc().unwrap();
+    assert!(!dropped.get());
+    let res: f64 = ctx.eval_string("answer()").unwrap().into();
+    assert_eq!(res, 42.0);
+
+    // Running the finalizer by hand from within the function doesn't pull the closure from
+    // under it
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    ctx.register_function("finalizeSelf", move |ctx, _args| {
+        ctx.eval_string("Duktape.fin(finalizeSelf)(finalizeSelf)")?;
+        Ok(Value::from(flag.0.get()))
+    })
+    .unwrap();
+    let res: bool = ctx.eval_string("finalizeSelf()").unwrap().try_into().unwrap();
+    assert!(!res);
+    assert!(dropped.get());
+}
+
+#[test]
+fn test_call_js_function() {
+    let ctx = Context::new().unwrap();
+    let add: Function = ctx
+        .eval_string("(function (a, b) { return a + b + (this.offset || 0); })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let val: i64 = add
+        .call(&Value::Undefined, &[Value::from(1_i64), Value::from(2_i64)])
+        .unwrap()
+        .into();
+    assert_eq!(val, 3);
+
+    let this = ctx.decode_json(r#"{"offset": 10}"#).unwrap();
+    let val: i64 = add.call(&this, &[Value::from(1_i64), Value::from(2_i64)]).unwrap().into();
+    assert_eq!(val, 13);
+}
+
+#[test]
+fn test_call_registered_callback() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var handlers = {}; function on(name, fn) { handlers[name] = fn; }")
+        .unwrap();
+    ctx.eval_string("on('greet', function (who) { return 'hi ' + who; })").unwrap();
+
+    let handlers: Object = ctx.eval_string("handlers").unwrap().try_into().unwrap();
+    let greet: Function = handlers.get("greet").unwrap().try_into().unwrap();
+    let res = greet.call(&Value::Undefined, &[Value::from("bob")]).unwrap();
+    assert_eq!(res.to_string(), "hi bob");
+}
+
+#[test]
+fn test_call_errors() {
+    let ctx = Context::new().unwrap();
+    let fail: Function = ctx
+        .eval_string("(function () { throw new RangeError('out of range'); })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let err = fail.call(&Value::Undefined, &[]).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+
+    let not_a_function: Result<Function, _> = ctx.eval_string("({})").unwrap().try_into();
+    assert_eq!(not_a_function.unwrap_err().code(), DukErrorCode::Type);
+    let not_a_function: Result<Function, _> = ctx.eval_string("42").unwrap().try_into();
+    assert!(not_a_function.is_err());
+}
+
+#[test]
+fn test_call_method() {
+    let ctx = Context::new().unwrap();
+    let counter: Object = ctx
+        .eval_string("({ n: 1, add: function (k) { this.n += k; return this.n; } })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let val: i64 = counter.call_method("add", &[Value::from(4_i64)]).unwrap().into();
+    assert_eq!(val, 5);
+    let n: i64 = counter.get("n").unwrap().into();
+    assert_eq!(n, 5);
+
+    let err = counter.call_method("missing", &[]).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_call_method_throwing_getter() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx
+        .eval_string("({ get f() { throw new RangeError('no f'); } })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let err = obj.call_method("f", &[Value::from(1_i64)]).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    let val: i64 = ctx.eval_string("1 + 1").unwrap().into();
+    assert_eq!(val, 2);
+}
+
+#[test]
+fn test_construct() {
+    let ctx = Context::new().unwrap();
+    let point: Function = ctx
+        .eval_string("(function Point(x, y) { this.x = x; this.y = y; })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let p = point.construct(&[Value::from(1_i64), Value::from(2_i64)]).unwrap();
+    assert_eq!(p.encode().unwrap(), r#"{"x":1,"y":2}"#);
+
+    let date: Function = ctx.eval_string("Date").unwrap().try_into().unwrap();
+    let epoch = date.construct(&[Value::from(0_i64)]).unwrap();
+    let time: i64 = epoch.call_method("getTime", &[]).unwrap().into();
+    assert_eq!(time, 0);
+}
+
+#[test]
+fn test_native_function_roundtrip() {
+    let ctx = Context::new().unwrap();
+    let double = ctx
+        .create_function(|_ctx, args| Ok(Value::from(arg_f64(args, 0) * 2.0)))
+        .unwrap();
+    let val: i64 = double.call(&Value::Undefined, &[Value::from(21_i64)]).unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_call_context_this() {
+    let ctx = Context::new().unwrap();
+    let describe = ctx
+        .create_native_function(|call| {
+            let this: Object = call.this()?.try_into()?;
+            let name = this.get("name")?;
+            Ok(Value::from(format!("{} ({} args)", name, call.args().len())))
+        })
+        .unwrap();
+    let thing: Object = ctx.eval_string("({ name: 'thing' })").unwrap().try_into().unwrap();
+    thing.set("describe", describe).unwrap();
+    let global: Object = ctx.eval_string("this").unwrap().try_into().unwrap();
+    global.set("thing", thing).unwrap();
+
+    let res = ctx.eval_string("thing.describe(1, 2)").unwrap().to_string();
+    assert_eq!(res, "thing (2 args)");
+}
+
+#[test]
+fn test_call_context_constructor() {
+    let ctx = Context::new().unwrap();
+    ctx.register_native_function("Counter", |call| {
+        if !call.is_constructor_call() {
+            return Err(DukError::from(DukErrorCode::Type, "Counter must be called with new"));
+        }
+        let this: Object = call.this()?.try_into()?;
+        let start = match call.args().first() {
+            Some(Value::Number(n)) => i64::from(n.clone()),
+            _ => 0,
+        };
+        this.set("count", start)?;
+        Ok(Value::Undefined)
+    })
+    .unwrap();
+
+    let count: i64 = ctx.eval_string("new Counter(3).count").unwrap().into();
+    assert_eq!(count, 3);
+    let err = ctx.eval_string("Counter(3)").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
+
+#[test]
+fn test_call_context_magic() {
+    let ctx = Context::new().unwrap();
+    let op = |call: &duktape::CallContext| {
+        let a = arg_f64(call.args(), 0);
+        let b = arg_f64(call.args(), 1);
+        let res = match call.magic() {
+            0 => a + b,
+            1 => a - b,
+            _ => return Err(DukError::from_str("unknown op")),
+        };
+        Ok(Value::from(res))
+    };
+    let add = ctx.create_native_function(op).unwrap();
+    let sub = ctx.create_native_function(op).unwrap();
+    sub.set_magic(1).unwrap();
+    assert_eq!(sub.magic().unwrap(), 1);
+
+    let global: Object = ctx.eval_string("this").unwrap().try_into().unwrap();
+    global.set("add", add).unwrap();
+    global.set("sub", sub.clone()).unwrap();
+    let val: i64 = ctx.eval_string("add(5, 3) * 10 + sub(5, 3)").unwrap().into();
+    assert_eq!(val, 82);
+
+    // The callee is the function being called
+    let same = ctx
+        .create_native_function(|call| Ok(Value::from(call.callee()?.magic()? as i64)))
+        .unwrap();
+    same.set_magic(7).unwrap();
+    let val: i64 = same.call(&Value::Undefined, &[]).unwrap().into();
+    assert_eq!(val, 7);
+
+    let js: Function = ctx.eval_string("(function () {})").unwrap().try_into().unwrap();
+    assert!(js.set_magic(1).is_err());
+    assert_eq!(js.magic().unwrap(), 0);
+}
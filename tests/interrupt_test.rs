@@ -0,0 +1,81 @@
+#![cfg(feature = "exec-timeout")]
+
+use duktape::{Context, ContextBuilder, ContextThread, DukErrorCode};
+use std::convert::TryInto;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_time_limit_aborts_script() {
+    let ctx = ContextBuilder::new()
+        .time_limit(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let start = Instant::now();
+    let err = ctx.eval_string("for (;;) {}").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // Each eval gets its own deadline
+    let val: i64 = ctx.eval_string("1 + 1").unwrap().into();
+    assert_eq!(val, 2);
+}
+
+#[test]
+fn test_timeout_is_not_reported_afterwards() {
+    let ctx = ContextBuilder::new()
+        .time_limit(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let err = ctx.eval_string("for (;;) {}").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Timeout);
+
+    let err = ctx.compile("var x = ;", "broken.js").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+    let err = ctx.decode_json("{").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+}
+
+#[test]
+fn test_timeout_cannot_be_caught() {
+    let ctx = Context::new().unwrap();
+    ctx.set_time_limit(Some(Duration::from_millis(50)));
+
+    let err = ctx
+        .eval_string("for (;;) { try { for (;;) {} } catch (e) {} }")
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Timeout);
+}
+
+#[test]
+fn test_interrupt_from_another_thread() {
+    let ctx = Context::new().unwrap();
+    let handle = ctx.interrupt_handle();
+
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.interrupt();
+    });
+
+    let err = ctx.eval_string("while (true) {}").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Interrupted);
+    interrupter.join().unwrap();
+
+    let val: bool = ctx.eval_string("true").unwrap().try_into().unwrap();
+    assert!(val);
+}
+
+#[test]
+fn test_interrupt_through_handle() {
+    let worker = ContextThread::spawn(Context::new).unwrap();
+    let handle = worker.handle();
+
+    let interrupter = handle.clone();
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        interrupter.interrupt();
+    });
+    assert!(handle.eval_string("for (;;) {}").is_err());
+    t.join().unwrap();
+}
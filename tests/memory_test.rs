@@ -0,0 +1,85 @@
+use duktape::{Context, ContextBuilder, DukErrorCode};
+
+#[test]
+fn test_memory_usage_is_tracked() {
+    let ctx = Context::new().unwrap();
+    let before = ctx.memory_usage();
+    assert!(before > 0);
+
+    ctx.eval_string("var data = []; for (var i = 0; i < 10000; i++) data.push('item' + i);")
+        .unwrap();
+    assert!(ctx.memory_usage() > before);
+
+    ctx.eval_string("data = null").unwrap();
+    assert!(ctx.peak_memory_usage() >= ctx.memory_usage());
+}
+
+#[test]
+fn test_memory_limit_exceeded() {
+    let ctx = ContextBuilder::new()
+        .memory_limit(1024 * 1024)
+        .build()
+        .unwrap();
+
+    let err = ctx
+        .eval_string("var a = []; while (true) a.push(new Array(1e6).join('x'));")
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::OutOfMemory);
+    assert!(ctx.peak_memory_usage() <= 1024 * 1024);
+
+    // The context remains usable once the garbage is gone
+    let val: i64 = ctx.eval_string("a = null; 1 + 2").unwrap().into();
+    assert_eq!(val, 3);
+}
+
+#[test]
+fn test_errors_after_caught_out_of_memory() {
+    let ctx = ContextBuilder::new()
+        .memory_limit(1024 * 1024)
+        .build()
+        .unwrap();
+
+    let err = ctx
+        .eval_string("try { new Array(1e7).join('x') } catch (e) {} null.foo")
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+    let err = ctx
+        .eval_string("try { new Array(1e7).join('x') } catch (e) {} new Array(-1)")
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+}
+
+#[test]
+fn test_set_memory_limit() {
+    let ctx = Context::new().unwrap();
+    assert_eq!(ctx.memory_limit(), None);
+
+    ctx.set_memory_limit(Some(ctx.memory_usage() + 64 * 1024));
+    let err = ctx
+        .eval_string("var s = 'x'; while (true) s = s + s;")
+        .unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::OutOfMemory);
+
+    ctx.set_memory_limit(None);
+    assert_eq!(ctx.memory_limit(), None);
+    let err = ctx.compile("var x = ;", "broken.js").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+}
+
+#[test]
+fn test_memory_limit_outside_scripts() {
+    let ctx = ContextBuilder::new()
+        .memory_limit(1024 * 1024)
+        .build()
+        .unwrap();
+    let obj = ctx.create_object().unwrap();
+    let big = "x".repeat(4 * 1024 * 1024);
+
+    // Strings pushed from Rust go over the limit without a script running
+    assert!(ctx.decode_json(&format!("\"{}\"", big)).is_err());
+    assert!(obj.set("big", big.as_str()).is_err());
+    assert!(obj.get(&big).is_err());
+
+    let val: i64 = ctx.eval_string("1 + 1").unwrap().into();
+    assert_eq!(val, 2);
+}
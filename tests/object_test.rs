@@ -1,4 +1,6 @@
-use duktape::{Context, Object, Value, Number};
+use duktape::{Context, DukError, DukErrorCode, FromJs, IntoJs, Number, Object, Value};
+use std::cell::Cell;
+use std::rc::Rc;
 use std::convert::TryInto;
 use std::error::Error;
 
@@ -113,3 +115,62 @@ fn test_get_prop_from_object() {
 
     assert_eq!(value.as_str(), "thing");
 }
+
+#[test]
+fn test_define_accessor() {
+    let ctx = Context::new().unwrap();
+    let config: Object = ctx.eval_string("config = ({ retries: 3 })").unwrap().try_into().unwrap();
+    let timeout = Rc::new(Cell::new(30_u32));
+    let (get, set) = (timeout.clone(), timeout.clone());
+    config
+        .define_accessor(
+            "timeout",
+            move |ctx| get.get().into_js(ctx),
+            move |_ctx, value| match u32::from_js(&value) {
+                Ok(secs) if secs > 0 => {
+                    set.set(secs);
+                    Ok(())
+                }
+                _ => Err(DukError::from(DukErrorCode::Range, "timeout must be a positive integer")),
+            },
+        )
+        .unwrap();
+
+    let value: u32 = FromJs::from_js(&ctx.eval_string("config.timeout").unwrap()).unwrap();
+    assert_eq!(value, 30);
+    timeout.set(45);
+    let value: u32 = FromJs::from_js(&ctx.eval_string("config.timeout").unwrap()).unwrap();
+    assert_eq!(value, 45);
+
+    ctx.eval_string("config.timeout = 10").unwrap();
+    assert_eq!(timeout.get(), 10);
+    let err = ctx.eval_string("config.timeout = -1").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    assert_eq!(timeout.get(), 10);
+
+    assert_eq!(config.encode().unwrap().as_str(), "{\"retries\":3,\"timeout\":10}");
+}
+
+#[test]
+fn test_define_getter() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx.eval_string("obj = ({})").unwrap().try_into().unwrap();
+    let reads = Rc::new(Cell::new(0_u32));
+    let counter = reads.clone();
+    obj.define_getter("reads", move |ctx| {
+        counter.set(counter.get() + 1);
+        counter.get().into_js(ctx)
+    })
+    .unwrap();
+
+    let value: u32 = FromJs::from_js(&ctx.eval_string("obj.reads; obj.reads").unwrap()).unwrap();
+    assert_eq!(value, 2);
+    ctx.eval_string("obj.reads = 10").unwrap();
+    assert_eq!(reads.get(), 2);
+    let err = ctx.eval_string("'use strict'; obj.reads = 10").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+
+    let frozen: Object = ctx.eval_string("Object.freeze({ a: 1 })").unwrap().try_into().unwrap();
+    let err = frozen.define_getter("a", |_ctx| Ok(Value::Null)).unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Type);
+}
@@ -0,0 +1,138 @@
+use duktape::{ContextBuilder, ContextPool};
+use std::convert::TryInto;
+
+fn pool() -> ContextPool {
+    ContextPool::builder()
+        .init(|ctx| ctx.eval_string("function double(x) { return x * 2; }").map(|_| ()))
+        .min_idle(2)
+        .max_idle(2)
+        .max_size(3)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_pool_warm_up() {
+    let pool = pool();
+    assert_eq!(pool.idle(), 2);
+    assert_eq!(pool.size(), 2);
+
+    let ctx = pool.get().unwrap();
+    let val: i64 = ctx.eval_string("double(21)").unwrap().into();
+    assert_eq!(val, 42);
+    assert_eq!(pool.idle(), 1);
+
+    drop(ctx);
+    assert_eq!(pool.idle(), 2);
+    assert_eq!(pool.size(), 2);
+}
+
+#[test]
+fn test_pool_resets_globals() {
+    let pool = pool();
+    {
+        let ctx = pool.get().unwrap();
+        ctx.eval_string("var leaked = 1; other = 2;").unwrap();
+    }
+    let ctx = pool.get().unwrap();
+    let kind: String = ctx.eval_string("typeof leaked + typeof other + typeof double")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(kind, "undefinedundefinedfunction");
+}
+
+#[test]
+fn test_pool_discards_dirty_contexts() {
+    let pool = pool();
+    {
+        let ctx = pool.get().unwrap();
+        ctx.eval_string("Object.defineProperty(this, 'stuck', {value: 1, configurable: false})")
+            .unwrap();
+    }
+    assert_eq!(pool.idle(), 1);
+    assert_eq!(pool.size(), 1);
+
+    pool.get().unwrap().discard();
+    assert_eq!(pool.size(), 0);
+}
+
+#[test]
+fn test_pool_reuses_contexts_after_errors() {
+    let pool = pool();
+    {
+        let ctx = pool.get().unwrap();
+        assert!(ctx.decode_json("{").is_err());
+        assert!(ctx.eval_string("throw new Error('oops')").is_err());
+    }
+    assert_eq!(pool.idle(), 2);
+    assert_eq!(pool.size(), 2);
+}
+
+#[test]
+fn test_pool_size_limit() {
+    let pool = pool();
+    let a = pool.get().unwrap();
+    let b = pool.get().unwrap();
+    let c = pool.get().unwrap();
+    assert!(pool.get().is_err());
+    drop((a, b, c));
+    assert_eq!(pool.idle(), 2);
+    assert_eq!(pool.size(), 2);
+}
+
+#[test]
+fn test_pool_restores_limits() {
+    let pool = ContextPool::builder()
+        .context_builder(|| ContextBuilder::new().memory_limit(8 * 1024 * 1024))
+        .build()
+        .unwrap();
+    {
+        let ctx = pool.get().unwrap();
+        ctx.set_memory_limit(None);
+    }
+    let ctx = pool.get().unwrap();
+    assert_eq!(ctx.memory_limit(), Some(8 * 1024 * 1024));
+}
+
+#[test]
+fn test_pool_discards_mutated_builtins() {
+    let scripts = [
+        "double = function (x) { return x * 3; }",
+        "JSON.stringify = function () { return '[]'; }",
+        "Array.prototype.leak = 'secret'",
+        "Object.defineProperty(Object.prototype, 'x', {get: function () { return 1; }, configurable: true})",
+        "Object.preventExtensions(Math)",
+        "Object.setPrototypeOf(double, null)",
+        "double.cache = {}",
+    ];
+    for script in &scripts {
+        let pool = pool();
+        {
+            let ctx = pool.get().unwrap();
+            ctx.eval_string(script).unwrap();
+        }
+        assert_eq!(pool.size(), 1, "{}", script);
+    }
+}
+
+#[test]
+fn test_pool_reset_does_not_rely_on_scripts() {
+    let pool = pool();
+    {
+        let ctx = pool.get().unwrap();
+        ctx.eval_string("var leaked = 1; Object.getOwnPropertyNames = function () { return []; };")
+            .unwrap();
+    }
+    // The overwritten builtin gets the context discarded
+    assert_eq!(pool.size(), 1);
+
+    {
+        let ctx = pool.get().unwrap();
+        ctx.eval_string("var leaked = 1;").unwrap();
+    }
+    assert_eq!(pool.size(), 1);
+    let ctx = pool.get().unwrap();
+    let kind: String = ctx.eval_string("typeof leaked").unwrap().try_into().unwrap();
+    assert_eq!(kind, "undefined");
+}
@@ -0,0 +1,117 @@
+use duktape::{CheckError, Context, ContextBuilder, DukErrorCode};
+
+#[test]
+fn test_compile_and_run() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var counter = 0").unwrap();
+
+    let script = ctx.compile("counter += 1; counter * 10", "counter.js").unwrap();
+    assert_eq!(script.filename(), "counter.js");
+
+    for i in 1..=3 {
+        let val: i64 = script.run().unwrap().into();
+        assert_eq!(val, i * 10);
+    }
+}
+
+#[test]
+fn test_compile_does_not_run() {
+    let ctx = Context::new().unwrap();
+    let _script = ctx.compile("var ran = true", "lazy.js").unwrap();
+    let kind = ctx.eval_string("typeof ran").unwrap().to_string();
+    assert_eq!(kind, "undefined");
+}
+
+#[test]
+fn test_syntax_error_has_filename() {
+    let ctx = Context::new().unwrap();
+    let err = ctx.compile("var x = ;", "broken.js").unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Syntax);
+    assert_eq!(err.file_name(), Some("broken.js"));
+    assert_eq!(err.line_number(), Some(1));
+}
+
+#[test]
+fn test_runtime_error_has_filename() {
+    let ctx = Context::new().unwrap();
+    let script = ctx
+        .compile("var a = 1;\nthrow new RangeError('out of range');", "rules/range.js")
+        .unwrap();
+
+    let err = script.run().unwrap_err();
+    assert_eq!(err.code(), DukErrorCode::Range);
+    assert_eq!(err.file_name(), Some("rules/range.js"));
+    assert_eq!(err.line_number(), Some(2));
+    assert!(err.to_string().contains("rules/range.js"));
+}
+
+#[test]
+fn test_bytecode_round_trip() {
+    let ctx = Context::new().unwrap();
+    let script = ctx
+        .compile("function add(a, b) { return a + b; }\nadd(40, 2)", "add.js")
+        .unwrap();
+    let bytecode = script.to_bytecode().unwrap();
+
+    // Load into a different heap
+    let other = Context::new().unwrap();
+    let loaded = unsafe { other.load_bytecode(&bytecode) }.unwrap();
+    assert_eq!(loaded.filename(), "add.js");
+    let val: i64 = loaded.run().unwrap().into();
+    assert_eq!(val, 42);
+    let val: i64 = loaded.run().unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_load_invalid_bytecode() {
+    let ctx = Context::new().unwrap();
+    assert!(unsafe { ctx.load_bytecode(b"") }.is_err());
+    assert!(unsafe { ctx.load_bytecode(b"not bytecode at all, clearly") }.is_err());
+
+    let mut bytecode = ctx.compile("1 + 1", "one.js").unwrap().to_bytecode().unwrap();
+    let last = bytecode.len() - 1;
+    bytecode[last] ^= 0xff;
+    let err = unsafe { ctx.load_bytecode(&bytecode) }.unwrap_err();
+    assert!(err.to_string().contains("checksum"));
+
+    // A different duktape version
+    let mut bytecode = ctx.compile("1 + 1", "one.js").unwrap().to_bytecode().unwrap();
+    bytecode[5] = bytecode[5].wrapping_add(1);
+    let err = unsafe { ctx.load_bytecode(&bytecode) }.unwrap_err();
+    assert!(err.to_string().contains("duktape"));
+
+    // A duktape built with another config
+    let mut bytecode = ctx.compile("1 + 1", "one.js").unwrap().to_bytecode().unwrap();
+    bytecode[10] = bytecode[10].wrapping_add(1);
+    let err = unsafe { ctx.load_bytecode(&bytecode) }.unwrap_err();
+    assert!(err.to_string().contains("build config"));
+}
+
+#[test]
+fn test_check_syntax() {
+    let ctx = Context::new().unwrap();
+    ctx.check_syntax("var ran = true; function f() { return 1; }", "ok.js").unwrap();
+    // Checking never runs the code
+    let kind = ctx.eval_string("typeof ran").unwrap().to_string();
+    assert_eq!(kind, "undefined");
+
+    let err = match ctx.check_syntax("var a = 1;\nvar b = 2;\nvar = 3;", "bad.js") {
+        Err(CheckError::Syntax(err)) => err,
+        other => panic!("expected a syntax error, got {:?}", other),
+    };
+    assert_eq!(err.line(), Some(3));
+    assert!(!err.message().is_empty());
+    assert!(!err.message().starts_with("SyntaxError"));
+    assert_eq!(err.column(), None);
+}
+
+#[test]
+fn test_check_syntax_reports_other_failures_apart() {
+    let ctx = ContextBuilder::new().memory_limit(1024 * 1024).build().unwrap();
+    let source = format!("var s = '{}';", "x".repeat(4 * 1024 * 1024));
+    match ctx.check_syntax(source, "big.js") {
+        Err(CheckError::Failed(err)) => assert_ne!(err.code(), DukErrorCode::Syntax),
+        other => panic!("expected a failed check, got {:?}", other),
+    }
+}
@@ -0,0 +1,79 @@
+use duktape::{Context, Object};
+use std::convert::TryInto;
+
+#[test]
+fn test_gc_releases_memory() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var garbage = []; for (var i = 0; i < 10000; i++) garbage.push({i: i});")
+        .unwrap();
+    let before = ctx.stats().unwrap();
+
+    ctx.eval_string("garbage = null").unwrap();
+    ctx.gc().unwrap();
+    ctx.gc_compact().unwrap();
+
+    let after = ctx.stats().unwrap();
+    assert!(after.allocated_bytes < before.allocated_bytes);
+    assert!(after.allocations < before.allocations);
+    assert_eq!(after.peak_allocated_bytes, before.peak_allocated_bytes);
+}
+
+#[test]
+fn test_stats_count_rust_objects() {
+    let ctx = Context::new().unwrap();
+    assert_eq!(ctx.stats().unwrap().rust_objects, 0);
+
+    let obj: Object = ctx.eval_string("({})").unwrap().try_into().unwrap();
+    let other = obj.clone();
+    assert_eq!(ctx.stats().unwrap().rust_objects, 1);
+
+    drop(obj);
+    drop(other);
+    assert_eq!(ctx.stats().unwrap().rust_objects, 0);
+}
+
+#[test]
+fn test_inspect_object() {
+    let ctx = Context::new().unwrap();
+    let obj: Object = ctx.eval_string("({a: 1, b: 2})").unwrap().try_into().unwrap();
+
+    let info = obj.inspect().unwrap();
+    assert!(info.heap_bytes.unwrap() > 0);
+    assert!(info.refcount.unwrap() > 0);
+    assert_eq!(info.bytecode_bytes, None);
+}
+
+#[test]
+fn test_stats_count_objects_and_strings() {
+    let ctx = Context::new().unwrap();
+    let before = ctx.stats().unwrap();
+    assert!(before.objects > 0);
+    assert!(before.strings > 0);
+    assert!(before.object_bytes > 0 && before.object_bytes <= before.allocated_bytes);
+
+    ctx.eval_string("var kept = []; for (var i = 0; i < 100; i++) { var o = {}; o['key' + i] = 'value' + i; kept.push(o); }")
+        .unwrap();
+    let after = ctx.stats().unwrap();
+    assert!(after.objects >= before.objects + 101);
+    assert!(after.strings >= before.strings + 200);
+    assert!(after.string_bytes > before.string_bytes);
+
+    // Objects held by Rust count too, even once unreachable from scripts
+    let held: Object = ctx.eval_string("kept.pop()").unwrap().try_into().unwrap();
+    ctx.eval_string("kept = null").unwrap();
+    ctx.gc().unwrap();
+    let last = ctx.stats().unwrap();
+    assert!(last.objects > before.objects && last.objects < after.objects);
+    drop(held);
+}
+
+#[test]
+fn test_stats_with_empty_objects() {
+    // The heap stash of a fresh context is empty
+    let ctx = Context::new().unwrap();
+    let fresh = ctx.stats().unwrap();
+    assert!(fresh.objects > 0);
+
+    ctx.eval_string("var o = {}").unwrap();
+    assert!(ctx.stats().unwrap().objects > fresh.objects);
+}
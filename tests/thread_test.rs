@@ -0,0 +1,131 @@
+use duktape::{Context, Coroutine, CoroutineState, Object, Value};
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+#[test]
+fn test_spawn_thread_shares_globals() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var shared = 'parent'").unwrap();
+
+    let thread = ctx.spawn_thread().unwrap();
+    assert!(thread.is_thread());
+    assert!(thread.ptr_eq(&ctx));
+
+    let val: String = thread.eval_string("shared").unwrap().try_into().unwrap();
+    assert_eq!(val, "parent");
+
+    thread.eval_string("shared = 'thread'").unwrap();
+    let val: String = ctx.eval_string("shared").unwrap().try_into().unwrap();
+    assert_eq!(val, "thread");
+}
+
+#[test]
+fn test_thread_keeps_heap_alive() {
+    let ctx = Context::new().unwrap();
+    let thread = ctx.spawn_thread().unwrap();
+    drop(ctx);
+
+    let val: i64 = thread.eval_string("6 * 7").unwrap().into();
+    assert_eq!(val, 42);
+}
+
+#[test]
+fn test_coroutine_yield_and_resume() {
+    let ctx = Context::new().unwrap();
+    let func: Object = ctx
+        .eval_string(
+            "(function (first) {
+                var second = Duktape.Thread.yield(first + 1);
+                var third = Duktape.Thread.yield(second + 1);
+                return first + second + third;
+            })",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    let co = Coroutine::new(&ctx, &func).unwrap();
+    match co.resume(1).unwrap() {
+        CoroutineState::Yielded(v) => assert_eq!(i64::from(v), 2),
+        s => panic!("Unexpected state {:?}", s),
+    }
+    match co.resume(10).unwrap() {
+        CoroutineState::Yielded(v) => assert_eq!(i64::from(v), 11),
+        s => panic!("Unexpected state {:?}", s),
+    }
+    match co.resume(100).unwrap() {
+        CoroutineState::Returned(v) => assert_eq!(i64::from(v), 111),
+        s => panic!("Unexpected state {:?}", s),
+    }
+    assert!(co.is_finished());
+    assert!(co.resume(0).is_err());
+}
+
+#[test]
+fn test_coroutine_error() {
+    let ctx = Context::new().unwrap();
+    let func: Object = ctx
+        .eval_string("(function () { Duktape.Thread.yield(1); throw new Error('failed'); })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    let co = Coroutine::new(&ctx, &func).unwrap();
+    assert!(co.resume(Value::Undefined).is_ok());
+    let err = co.resume(Value::Undefined).unwrap_err();
+    assert!(err.to_string().contains("failed"));
+    assert!(co.is_finished());
+}
+
+#[test]
+fn test_object_wrappers_share_stash_entry() {
+    let ctx = Context::new().unwrap();
+    ctx.eval_string("var tmp = {value: 1}").unwrap();
+
+    let first: Object = ctx.eval_string("tmp").unwrap().try_into().unwrap();
+    let second: Object = ctx.eval_string("tmp").unwrap().try_into().unwrap();
+    let third = second.clone();
+    drop(first);
+    drop(second);
+    ctx.eval_string("tmp = null; Duktape.gc()").unwrap();
+
+    let value: i64 = third.get("value").unwrap().into();
+    assert_eq!(value, 1);
+}
+
+#[test]
+fn test_objects_from_finished_coroutine() {
+    let ctx = Context::new().unwrap();
+    let kept = Rc::new(RefCell::new(None));
+    let store = kept.clone();
+    ctx.register_function("keep", move |_ctx, args| {
+        if let Some(Value::Object(obj)) = args.first() {
+            *store.borrow_mut() = Some(obj.clone());
+        }
+        Ok(Value::Undefined)
+    })
+    .unwrap();
+    let func: Object = ctx
+        .eval_string("(function () { keep({ answer: 42 }); Duktape.Thread.yield(0); })")
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    let co = Coroutine::new(&ctx, &func).unwrap();
+    assert!(co.resume(Value::Undefined).is_ok());
+    let obj = kept.borrow_mut().take().unwrap();
+    let val: i64 = obj.get("answer").unwrap().into();
+    assert_eq!(val, 42);
+
+    assert!(co.resume(Value::Undefined).is_ok());
+    assert!(co.is_finished());
+    drop(co);
+    drop(func);
+    ctx.eval_string("Duktape.gc(); Duktape.gc()").unwrap();
+
+    obj.set("answer", 7_i64).unwrap();
+    let val: i64 = obj.get("answer").unwrap().into();
+    assert_eq!(val, 7);
+    drop(obj);
+}